@@ -0,0 +1,234 @@
+use clap::{Parser, ValueEnum};
+use std::path::{Path, PathBuf};
+use tanour::abi::encode_message;
+use tanour::chain::FileChain;
+use tanour::codec;
+use tanour::contract::{Contract, Params};
+use tanour::error::backtrace;
+use tanour::gas::GasSchedule;
+use tanour::trace::{replay, Trace, TraceRecorder};
+use tanour::{address_from_bytes, Address};
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Action {
+    Instantiate,
+    Process,
+    Query,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ProfileFormat {
+    /// A table of the functions with the inclusive and exclusive points
+    Report,
+    /// The folded stacks, used by the flamegraph tools
+    Folded,
+}
+
+/// Executes a contract locally, the contract storage is kept in the storage directory.
+#[derive(Debug, Parser)]
+#[command(name = "tanour-cli", version)]
+struct Args {
+    /// Path to the contract's wasm file
+    #[arg(long)]
+    wasm: PathBuf,
+
+    /// Address of the contract in hex
+    #[arg(long, value_parser = parse_address)]
+    address: Address,
+
+    /// The action to execute
+    #[arg(long, value_enum, required_unless_present = "replay")]
+    action: Option<Action>,
+
+    /// The encoded arguments in hex
+    #[arg(long, conflicts_with_all = ["args_file", "json"])]
+    args: Option<String>,
+
+    /// Path to a file containing the CBOR encoded arguments
+    #[arg(long, conflicts_with = "json")]
+    args_file: Option<PathBuf>,
+
+    /// The arguments in JSON, they are encoded into CBOR.
+    /// If the contract has an ABI, the arguments are validated and encoded by the ABI,
+    /// otherwise byte strings are written as "h'<hex>'".
+    #[arg(long)]
+    json: Option<String>,
+
+    /// The directory to keep the contracts' storage
+    #[arg(long, default_value = "./storage")]
+    storage: PathBuf,
+
+    /// The storage page size in bytes
+    #[arg(long, default_value_t = 256)]
+    page_size: u32,
+
+    /// The maximum memory of the contract in Wasm pages (64 KiB each)
+    #[arg(long, default_value_t = 16)]
+    memory_limit_page: u32,
+
+    /// The maximum metering points that can be consumed
+    #[arg(long, default_value_t = 1_000_000)]
+    gas: u64,
+
+    /// The maximum stack height, in the values that the function frames keep
+    #[arg(long, default_value_t = 32768)]
+    stack_height_limit: u32,
+
+    /// The maximum storage size of the contract in bytes
+    #[arg(long, default_value_t = 1024 * 1024)]
+    storage_limit: u32,
+
+    /// Path to a file to write the execution trace into
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Path to a trace file to replay. The storage is read from the trace
+    /// and the results and the gas usage are verified.
+    #[arg(long, conflicts_with_all = ["action", "trace"])]
+    replay: Option<PathBuf>,
+
+    /// Prints the metering points that each function consumes
+    #[arg(long, value_enum)]
+    profile: Option<ProfileFormat>,
+
+    /// Path to a file to write the profile into, instead of printing it
+    #[arg(long, requires = "profile")]
+    profile_output: Option<PathBuf>,
+}
+
+fn parse_address(s: &str) -> Result<Address, String> {
+    let bytes = hex::decode(s).map_err(|err| format!("{err}"))?;
+    if bytes.len() != tanour::ADDRESS_SIZE {
+        return Err(format!(
+            "invalid address length: {}, expected: {}",
+            bytes.len(),
+            tanour::ADDRESS_SIZE
+        ));
+    }
+    Ok(address_from_bytes(&bytes))
+}
+
+fn read_args(
+    args: &Args,
+    action: Action,
+    contract: &Contract,
+) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    if let Some(hex_args) = &args.args {
+        return Ok(hex::decode(hex_args)?);
+    }
+    if let Some(path) = &args.args_file {
+        return Ok(std::fs::read(path)?);
+    }
+    if let Some(json) = &args.json {
+        let entry = match action {
+            Action::Instantiate => "instantiate",
+            Action::Process => "process",
+            Action::Query => "query",
+        };
+        return Ok(encode_message(contract.abi()?, entry, json)?);
+    }
+    Ok(Vec::new())
+}
+
+fn make_params(args: &Args) -> Params {
+    Params {
+        memory_limit_page: args.memory_limit_page,
+        metering_limit: args.gas,
+        stack_height_limit: args.stack_height_limit,
+        storage_limit: args.storage_limit,
+        gas_schedule: GasSchedule::default(),
+        module_cache: None,
+        trace: args.trace.as_ref().map(|_| TraceRecorder::new()),
+        profiling: args.profile.is_some(),
+    }
+}
+
+fn run_replay(args: &Args, code: &[u8], path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let trace = Trace::read_file(path)?;
+    let replayed = replay(&args.address, code, &trace, make_params(args))?;
+
+    for (function, arg) in replayed.calls() {
+        println!("{function}: {}", hex::encode(arg));
+    }
+    println!(
+        "replayed {} calls, the results are matched",
+        replayed.calls().count()
+    );
+    Ok(())
+}
+
+fn run(args: &Args) -> Result<(), Box<dyn std::error::Error>> {
+    let code = std::fs::read(&args.wasm)?;
+    let action = match (&args.replay, args.action) {
+        (Some(path), _) => return run_replay(args, &code, path),
+        (None, Some(action)) => action,
+        (None, None) => return Err("the action is not set".into()),
+    };
+    let chain = FileChain::new(&args.storage, &args.address, args.page_size)?;
+
+    let mut contract = Contract::new(Box::new(chain), &args.address, &code, make_params(args))?;
+    let encoded_arg = read_args(args, action, &contract)?;
+    let result = match action {
+        Action::Instantiate => contract.call_instantiate(&encoded_arg),
+        Action::Process => contract.call_process(&encoded_arg),
+        Action::Query => contract.call_query(&encoded_arg),
+    };
+
+    println!("gas used: {}", contract.consumed_points()?);
+    if let (Some(path), Some(trace)) = (&args.trace, contract.trace()) {
+        trace.write_file(path)?;
+        println!("trace is written into: {}", path.display());
+    }
+    if let (Some(format), Some(profile)) = (args.profile, contract.profile()?) {
+        let text = match format {
+            ProfileFormat::Report => profile.report(),
+            ProfileFormat::Folded => profile.folded(),
+        };
+        match &args.profile_output {
+            Some(path) => {
+                std::fs::write(path, text)?;
+                println!("profile is written into: {}", path.display());
+            }
+            None => print!("{text}"),
+        }
+    }
+    let data = match result {
+        Ok(data) => data,
+        Err(err) => {
+            // It is printed to stderr by `main`, with the backtrace of the contract
+            let msg = format!("error ({}): {err}\n{}", err.code(), backtrace(err.frames()));
+            return Err(msg.trim_end().into());
+        }
+    };
+    println!("result: {}", hex::encode(&data));
+    if let Ok(json) = codec::cbor_to_json(&data) {
+        println!("decoded result: {json}");
+    }
+
+    if let Action::Query = action {
+        return Ok(());
+    }
+
+    println!("storage growth: {} bytes", contract.storage_growth()?);
+    println!("storage changes:");
+    for diff in contract.storage_diffs()? {
+        println!(
+            "  {}: {} -> {}",
+            diff.offset,
+            hex::encode(&diff.old_data),
+            hex::encode(&diff.new_data)
+        );
+    }
+    contract.commit()?;
+
+    Ok(())
+}
+
+fn main() {
+    let args = Args::parse();
+
+    if let Err(err) = run(&args) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
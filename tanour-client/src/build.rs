@@ -0,0 +1,7 @@
+fn main() {
+    ::capnpc::CompilerCommand::new()
+        .src_prefix("../tanour-server")
+        .file("../tanour-server/tanour.capnp")
+        .run()
+        .unwrap();
+}
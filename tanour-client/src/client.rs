@@ -0,0 +1,365 @@
+use crate::error::{Error, Result};
+use crate::provider::{Provider, ProviderServer};
+use crate::tanour_capnp::{executor, result_data, transaction};
+use crate::{Action, Address, ExecutionResult, Frame, Status, StorageDiff, Transaction};
+use capnp_rpc::{rpc_twoparty_capnp, twoparty, RpcSystem};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, TryFutureExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+
+/// The client of the Tanour server.
+///
+/// The RPC system is spawned on the current `LocalSet`,
+/// hence the client should be created and used inside a `LocalSet`.
+pub struct TanourClient {
+    executor: executor::Client,
+}
+
+impl TanourClient {
+    /// Connects to the Tanour server at the given address.
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        let (reader, writer) = tokio_util::compat::TokioAsyncReadCompatExt::compat(stream).split();
+        Ok(Self::new(reader, writer))
+    }
+
+    /// Creates a client over the given connection to the Tanour server.
+    pub fn new<R, W>(reader: R, writer: W) -> Self
+    where
+        R: AsyncRead + Unpin + 'static,
+        W: AsyncWrite + Unpin + 'static,
+    {
+        let network = twoparty::VatNetwork::new(
+            reader,
+            writer,
+            rpc_twoparty_capnp::Side::Client,
+            Default::default(),
+        );
+
+        let mut rpc_system = RpcSystem::new(Box::new(network), None);
+        let executor: executor::Client = rpc_system.bootstrap(rpc_twoparty_capnp::Side::Server);
+        tokio::task::spawn_local(rpc_system.map_err(|err| log::error!("rpc_system error : {err}")));
+
+        TanourClient { executor }
+    }
+
+    /// Executes the transaction on the server.
+    /// The server reads and writes the contract state through the provider.
+    pub async fn execute<P: Provider>(
+        &self,
+        transaction: &Transaction,
+        provider: P,
+    ) -> Result<ExecutionResult> {
+        let mut req = self.executor.execute_request();
+        req.get()
+            .set_provider(capnp_rpc::new_client(ProviderServer::new(provider)));
+        set_transaction(req.get().init_transaction(), transaction);
+
+        let response = req.send().promise.await?;
+        let result_data = response.get()?.get_result_data()?;
+        read_result_data(result_data)
+    }
+
+    /// Returns the ABI of the contract code in JSON, if the code has an ABI.
+    pub async fn describe(&self, code: &[u8]) -> Result<Option<String>> {
+        let mut req = self.executor.describe_request();
+        req.get().set_code(code);
+
+        let response = req.send().promise.await?;
+        let abi = response.get()?.get_abi()?.to_string();
+        if abi.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(abi))
+    }
+}
+
+fn set_transaction(mut builder: transaction::Builder, transaction: &Transaction) {
+    builder.set_sender(&transaction.sender);
+    builder.set_value(transaction.value);
+    builder.set_gas(transaction.gas);
+    builder.set_gas_price(transaction.gas_price);
+    builder.set_address(&transaction.address);
+    builder.set_code(&transaction.code);
+    builder.set_args(&transaction.args);
+    builder.set_salt(&transaction.salt);
+    builder.set_id(&transaction.id);
+
+    let mut action = builder.init_action();
+    match transaction.action {
+        Action::Instantiate => action.set_instantiate(()),
+        Action::Process => action.set_process(()),
+        Action::Query => action.set_query(()),
+        Action::Deploy => action.set_deploy(()),
+    }
+}
+
+fn read_address(data: &[u8]) -> Result<Address> {
+    data.try_into().map_err(|_| Error::InvalidResult {
+        msg: format!("invalid address length: {}", data.len()),
+    })
+}
+
+fn read_result_data(reader: result_data::Reader) -> Result<ExecutionResult> {
+    let status = match reader.get_status().which()? {
+        result_data::status::Success(()) => Status::Success,
+        result_data::status::Error(error) => {
+            let error = error?;
+            let mut frames = Vec::new();
+            for frame in error.get_frames()?.iter() {
+                let func_name = frame.get_func_name()?.to_string();
+                let func_name = (!func_name.is_empty()).then_some(func_name);
+                frames.push(Frame {
+                    func_index: frame.get_func_index(),
+                    func_name,
+                    module_offset: frame.get_module_offset(),
+                    func_offset: frame.get_func_offset(),
+                });
+            }
+            Status::Error {
+                code: error.get_code(),
+                message: error.get_message()?.to_string(),
+                frames,
+            }
+        }
+        result_data::status::OutOfGas(()) => Status::OutOfGas,
+    };
+
+    let contract = match reader.get_contract()? {
+        data if data.is_empty() => None,
+        data => Some(read_address(data)?),
+    };
+
+    let mut pages = Vec::new();
+    for page in reader.get_pages()?.iter() {
+        pages.push((page.get_page_no(), page.get_data()?.to_vec()));
+    }
+
+    let mut diffs = Vec::new();
+    for diff in reader.get_diffs()?.iter() {
+        diffs.push(StorageDiff {
+            offset: diff.get_offset(),
+            old_data: diff.get_old_data()?.to_vec(),
+            new_data: diff.get_new_data()?.to_vec(),
+        });
+    }
+
+    Ok(ExecutionResult {
+        status,
+        gas_left: reader.get_gas_left(),
+        gas_used: reader.get_gas_used(),
+        data: reader.get_data()?.to_vec(),
+        contract,
+        pages,
+        storage_growth: reader.get_storage_growth(),
+        diffs,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tanour_capnp::provider;
+    use crate::Account;
+    use capnp::capability::Promise;
+    use capnp_rpc::pry;
+
+    struct TestProvider;
+
+    impl Provider for TestProvider {
+        fn page_size(&self) -> Result<u32> {
+            Ok(4)
+        }
+
+        fn storage_size(&self) -> Result<u32> {
+            Ok(8)
+        }
+
+        fn read_page(&self, page_no: u32) -> Result<Vec<u8>> {
+            Ok(vec![page_no as u8; 4])
+        }
+
+        fn write_page(&self, _page_no: u32, _data: &[u8]) -> Result<()> {
+            Ok(())
+        }
+
+        fn exist(&self, _address: &Address) -> Result<bool> {
+            Ok(true)
+        }
+
+        fn account(&self, _address: &Address) -> Result<Account> {
+            Ok(Account {
+                sequence: 1,
+                balance: 1000,
+                code: Vec::new(),
+            })
+        }
+    }
+
+    /// Executes the transactions by calling the provider and echoing the transaction back.
+    struct TestExecutor;
+
+    async fn call_provider(
+        provider: provider::Client,
+        address: Vec<u8>,
+        args: Vec<u8>,
+        mut results: executor::ExecuteResults,
+    ) -> std::result::Result<(), capnp::Error> {
+        let mut req = provider.read_page_request();
+        req.get().set_page_no(1);
+        let response = req.send().promise.await?;
+        let page = response.get()?.get_data()?.to_vec();
+
+        let mut req = provider.account_request();
+        req.get().set_address(&address);
+        let response = req.send().promise.await?;
+        let balance = response.get()?.get_account()?.get_balance();
+
+        let mut result_data = results.get().init_result_data();
+        if args.is_empty() {
+            let mut error = result_data.init_status().init_error();
+            error.set_code(9);
+            error.set_message("Trap: UnreachableCodeReached, unreachable");
+            let mut frame = error.init_frames(1).get(0);
+            frame.set_func_index(3);
+            frame.set_func_name("divide");
+            frame.set_module_offset(0x1a2);
+            frame.set_func_offset(0x12);
+            return Ok(());
+        }
+        result_data.set_gas_left(balance);
+        result_data.set_gas_used(args.len() as u64);
+        result_data.set_data(&args);
+        result_data.set_contract(&address);
+        result_data.set_storage_growth(4);
+        result_data.reborrow().init_status().set_success(());
+        let mut pages = result_data.reborrow().init_pages(1);
+        pages.reborrow().get(0).set_page_no(1);
+        pages.reborrow().get(0).set_data(&page);
+        let mut diff = result_data.init_diffs(1).get(0);
+        diff.set_offset(4);
+        diff.set_old_data(&page);
+        diff.set_new_data(&args);
+        Ok(())
+    }
+
+    impl executor::Server for TestExecutor {
+        fn execute(
+            &mut self,
+            params: executor::ExecuteParams,
+            results: executor::ExecuteResults,
+        ) -> Promise<(), capnp::Error> {
+            let provider = pry!(pry!(params.get()).get_provider());
+            let transaction = pry!(pry!(params.get()).get_transaction());
+            let address = pry!(transaction.get_address()).to_vec();
+            let args = pry!(transaction.get_args()).to_vec();
+            Promise::from_future(call_provider(provider, address, args, results))
+        }
+
+        fn describe(
+            &mut self,
+            params: executor::DescribeParams,
+            mut results: executor::DescribeResults,
+        ) -> Promise<(), capnp::Error> {
+            let code = pry!(pry!(params.get()).get_code());
+            if !code.is_empty() {
+                results.get().set_abi(r#"{"functions":[]}"#);
+            }
+            Promise::ok(())
+        }
+    }
+
+    /// Serves the test executor on one end of an in-memory stream
+    /// and returns a client connected to the other end.
+    fn make_test_client() -> TanourClient {
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+
+        let (reader, writer) =
+            tokio_util::compat::TokioAsyncReadCompatExt::compat(server_stream).split();
+        let network = twoparty::VatNetwork::new(
+            reader,
+            writer,
+            rpc_twoparty_capnp::Side::Server,
+            Default::default(),
+        );
+        let executor_client: executor::Client = capnp_rpc::new_client(TestExecutor);
+        let rpc_system = RpcSystem::new(Box::new(network), Some(executor_client.client));
+        tokio::task::spawn_local(rpc_system.map_err(|err| log::error!("rpc_system error : {err}")));
+
+        let (reader, writer) =
+            tokio_util::compat::TokioAsyncReadCompatExt::compat(client_stream).split();
+        TanourClient::new(reader, writer)
+    }
+
+    #[tokio::test]
+    async fn test_execute() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let client = make_test_client();
+                let transaction = Transaction {
+                    id: vec![1],
+                    sender: [1; 21],
+                    value: 0,
+                    gas: 1000,
+                    gas_price: 1,
+                    address: [2; 21],
+                    code: Vec::new(),
+                    action: Action::Process,
+                    args: vec![5, 6, 7],
+                    salt: Vec::new(),
+                };
+
+                let result = client.execute(&transaction, TestProvider).await.unwrap();
+                assert_eq!(result.status, Status::Success);
+                assert_eq!(result.gas_left, 1000);
+                assert_eq!(result.gas_used, 3);
+                assert_eq!(result.data, vec![5, 6, 7]);
+                assert_eq!(result.contract, Some([2; 21]));
+                assert_eq!(result.pages, vec![(1, vec![1; 4])]);
+                assert_eq!(result.storage_growth, 4);
+                assert_eq!(
+                    result.diffs,
+                    vec![StorageDiff {
+                        offset: 4,
+                        old_data: vec![1; 4],
+                        new_data: vec![5, 6, 7],
+                    }]
+                );
+
+                let transaction = Transaction {
+                    args: Vec::new(),
+                    ..transaction
+                };
+                let result = client.execute(&transaction, TestProvider).await.unwrap();
+                assert_eq!(
+                    result.status,
+                    Status::Error {
+                        code: 9,
+                        message: "Trap: UnreachableCodeReached, unreachable".to_string(),
+                        frames: vec![Frame {
+                            func_index: 3,
+                            func_name: Some("divide".to_string()),
+                            module_offset: 0x1a2,
+                            func_offset: 0x12,
+                        }],
+                    }
+                );
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_describe() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let client = make_test_client();
+
+                let abi = client.describe(&[0, 1]).await.unwrap();
+                assert_eq!(abi.as_deref(), Some(r#"{"functions":[]}"#));
+                assert_eq!(client.describe(&[]).await.unwrap(), None);
+            })
+            .await;
+    }
+}
@@ -0,0 +1,21 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("io error: {0}")]
+    IOError(#[from] std::io::Error),
+
+    #[error("RPC error: {0}")]
+    RpcError(#[from] capnp::Error),
+
+    #[error("Provider error: {msg}")]
+    ProviderError { msg: String },
+
+    #[error("Invalid result: {msg}")]
+    InvalidResult { msg: String },
+
+    #[error(transparent)]
+    CodecError(#[from] tanour_codec::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,103 @@
+//! A Rust client for the Tanour server.
+//!
+//! The types of this crate mirror the structs of `tanour.capnp`. They are not shared with
+//! the server, because the server converts them into the executor types of the `tanour` crate,
+//! and the clients should not depend on the Wasm runtime.
+
+pub mod tanour_capnp {
+    include!(concat!(env!("OUT_DIR"), "/tanour_capnp.rs"));
+}
+
+mod client;
+mod error;
+mod provider;
+
+pub use client::*;
+pub use error::{Error, Result};
+pub use provider::Provider;
+pub use tanour_codec::{cbor_to_json, json_to_cbor};
+
+pub const ADDRESS_SIZE: usize = 21;
+pub type Address = [u8; ADDRESS_SIZE];
+
+/// The action that the transaction executes on the contract.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Instantiate,
+    Process,
+    Query,
+    /// Deploys the code as a new contract and instantiates it.
+    /// The contract address is derived from the sender, the code and the salt.
+    Deploy,
+}
+
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    /// The transaction id, it is used to correlate the server logs and traces
+    pub id: Vec<u8>,
+    pub sender: Address,
+    pub value: u64,
+    pub gas: u64,
+    pub gas_price: u64,
+    pub address: Address,
+    pub code: Vec<u8>,
+    pub action: Action,
+    pub args: Vec<u8>,
+    pub salt: Vec<u8>,
+}
+
+/// The account of an address, it is served by the `account` call of the provider.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Account {
+    pub sequence: u64,
+    pub balance: u64,
+    pub code: Vec<u8>,
+}
+
+/// A changed byte range of the contract storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageDiff {
+    pub offset: u32,
+    pub old_data: Vec<u8>,
+    pub new_data: Vec<u8>,
+}
+
+/// A frame of the Wasm call stack when the execution trapped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub func_index: u32,
+    /// The name of the function in the name section, if the module has it
+    pub func_name: Option<String>,
+    pub module_offset: u64,
+    pub func_offset: u64,
+}
+
+/// The status of the execution.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Success,
+    /// The contract failed, the code is the stable error code of the executor.
+    /// The frames are the call stack if the execution trapped, the innermost frame comes first.
+    Error {
+        code: u32,
+        message: String,
+        frames: Vec<Frame>,
+    },
+    OutOfGas,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExecutionResult {
+    pub status: Status,
+    pub gas_left: u64,
+    pub gas_used: u64,
+    pub data: Vec<u8>,
+    /// The address of the deployed contract
+    pub contract: Option<Address>,
+    /// The initial storage pages of the deployed contract, empty data means a zero page
+    pub pages: Vec<(u32, Vec<u8>)>,
+    /// The number of bytes that the storage has grown
+    pub storage_growth: u32,
+    /// The changed byte ranges of the storage
+    pub diffs: Vec<StorageDiff>,
+}
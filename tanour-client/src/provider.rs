@@ -0,0 +1,151 @@
+use crate::error::{Error, Result};
+use crate::tanour_capnp::provider;
+use crate::{Account, Address};
+use capnp::capability::Promise;
+use capnp_rpc::pry;
+
+/// The blockchain state that the server reads and writes during the execution.
+/// An empty page data means a zero page, which all its bytes are zero.
+pub trait Provider: 'static {
+    fn page_size(&self) -> Result<u32>;
+    fn storage_size(&self) -> Result<u32>;
+    fn read_page(&self, page_no: u32) -> Result<Vec<u8>>;
+    fn read_pages(&self, page_nos: &[u32]) -> Result<Vec<Vec<u8>>> {
+        page_nos
+            .iter()
+            .map(|page_no| self.read_page(*page_no))
+            .collect()
+    }
+    fn write_page(&self, page_no: u32, data: &[u8]) -> Result<()>;
+    fn write_pages(&self, pages: &[(u32, Vec<u8>)]) -> Result<()> {
+        for (page_no, data) in pages {
+            self.write_page(*page_no, data)?;
+        }
+        Ok(())
+    }
+    fn exist(&self, address: &Address) -> Result<bool>;
+    /// Returns the account of the address.
+    /// The server doesn't call it yet, so it fails by default.
+    fn account(&self, address: &Address) -> Result<Account> {
+        Err(Error::ProviderError {
+            msg: format!("account is not provided: {address:?}"),
+        })
+    }
+}
+
+/// Serves the provider as the Cap'n Proto `Provider` capability.
+pub(crate) struct ProviderServer<P: Provider> {
+    provider: P,
+}
+
+impl<P: Provider> ProviderServer<P> {
+    pub fn new(provider: P) -> Self {
+        ProviderServer { provider }
+    }
+}
+
+fn rpc_error(err: Error) -> capnp::Error {
+    capnp::Error::failed(format!("{err}"))
+}
+
+fn read_address(data: &[u8]) -> std::result::Result<Address, capnp::Error> {
+    data.try_into()
+        .map_err(|_| capnp::Error::failed(format!("invalid address: {data:?}")))
+}
+
+impl<P: Provider> provider::Server for ProviderServer<P> {
+    fn page_size(
+        &mut self,
+        _: provider::PageSizeParams,
+        mut results: provider::PageSizeResults,
+    ) -> Promise<(), capnp::Error> {
+        let size = pry!(self.provider.page_size().map_err(rpc_error));
+        results.get().set_size(size);
+        Promise::ok(())
+    }
+
+    fn storage_size(
+        &mut self,
+        _: provider::StorageSizeParams,
+        mut results: provider::StorageSizeResults,
+    ) -> Promise<(), capnp::Error> {
+        let size = pry!(self.provider.storage_size().map_err(rpc_error));
+        results.get().set_size(size);
+        Promise::ok(())
+    }
+
+    fn read_page(
+        &mut self,
+        params: provider::ReadPageParams,
+        mut results: provider::ReadPageResults,
+    ) -> Promise<(), capnp::Error> {
+        let page_no = pry!(params.get()).get_page_no();
+        let data = pry!(self.provider.read_page(page_no).map_err(rpc_error));
+        results.get().set_data(&data);
+        Promise::ok(())
+    }
+
+    fn read_pages(
+        &mut self,
+        params: provider::ReadPagesParams,
+        mut results: provider::ReadPagesResults,
+    ) -> Promise<(), capnp::Error> {
+        let page_nos: Vec<u32> = pry!(pry!(params.get()).get_page_nos()).iter().collect();
+        let pages = pry!(self.provider.read_pages(&page_nos).map_err(rpc_error));
+        let mut list = results.get().init_pages(pages.len() as u32);
+        for (i, data) in pages.iter().enumerate() {
+            list.set(i as u32, data);
+        }
+        Promise::ok(())
+    }
+
+    fn write_page(
+        &mut self,
+        params: provider::WritePageParams,
+        _: provider::WritePageResults,
+    ) -> Promise<(), capnp::Error> {
+        let params = pry!(params.get());
+        let page_no = params.get_page_no();
+        let data = pry!(params.get_data());
+        pry!(self.provider.write_page(page_no, data).map_err(rpc_error));
+        Promise::ok(())
+    }
+
+    fn write_pages(
+        &mut self,
+        params: provider::WritePagesParams,
+        _: provider::WritePagesResults,
+    ) -> Promise<(), capnp::Error> {
+        let mut pages = Vec::new();
+        for page in pry!(pry!(params.get()).get_pages()).iter() {
+            pages.push((page.get_page_no(), pry!(page.get_data()).to_vec()));
+        }
+        pry!(self.provider.write_pages(&pages).map_err(rpc_error));
+        Promise::ok(())
+    }
+
+    fn exists(
+        &mut self,
+        params: provider::ExistsParams,
+        mut results: provider::ExistsResults,
+    ) -> Promise<(), capnp::Error> {
+        let address = pry!(read_address(pry!(pry!(params.get()).get_address())));
+        let exist = pry!(self.provider.exist(&address).map_err(rpc_error));
+        results.get().set_exist(exist);
+        Promise::ok(())
+    }
+
+    fn account(
+        &mut self,
+        params: provider::AccountParams,
+        mut results: provider::AccountResults,
+    ) -> Promise<(), capnp::Error> {
+        let address = pry!(read_address(pry!(pry!(params.get()).get_address())));
+        let account = pry!(self.provider.account(&address).map_err(rpc_error));
+        let mut builder = results.get().init_account();
+        builder.set_sequence(account.sequence);
+        builder.set_balance(account.balance);
+        builder.set_code(&account.code);
+        Promise::ok(())
+    }
+}
@@ -0,0 +1,9 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Codec error: {msg}")]
+    CodecError { msg: String },
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
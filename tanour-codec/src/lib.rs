@@ -0,0 +1,284 @@
+//! Converting the contract's arguments and results between JSON and CBOR.
+//!
+//! JSON values are mapped to the equivalent CBOR items.
+//! Since JSON has no byte strings, they are written as `h'<hex>'` strings,
+//! similar to the CBOR diagnostic notation.
+//! Object keys that are unsigned integers are encoded as integer keys.
+//! Object keys keep their order, so the CBOR maps are encoded in the same order as the JSON text.
+//! The nesting of the decoded arrays, maps and tags is limited, since the data may be untrusted.
+//!
+//! It doesn't depend on the Wasm runtime, so it can be used by the clients as well.
+
+mod error;
+
+pub use error::{Error, Result};
+use minicbor::data::Type;
+use minicbor::{Decoder, Encoder};
+use serde_json::{Map, Number, Value};
+
+/// The maximum nesting of the arrays, maps and tags that can be decoded.
+/// It is the same as the recursion limit of the JSON parser.
+const MAX_DEPTH: usize = 128;
+
+fn codec_error<E: std::fmt::Display>(original: E) -> Error {
+    Error::CodecError {
+        msg: format!("{original}"),
+    }
+}
+
+/// Parses the JSON text and encodes it into CBOR.
+pub fn json_to_cbor(json: &str) -> Result<Vec<u8>> {
+    let value: Value = serde_json::from_str(json).map_err(codec_error)?;
+    value_to_cbor(&value)
+}
+
+/// Decodes the CBOR data into a JSON text.
+pub fn cbor_to_json(cbor: &[u8]) -> Result<String> {
+    let value = cbor_to_value(cbor)?;
+    serde_json::to_string(&value).map_err(codec_error)
+}
+
+/// Encodes the JSON value into CBOR.
+pub fn value_to_cbor(value: &Value) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    let mut encoder = Encoder::new(&mut buf);
+    encode_value(&mut encoder, value)?;
+    Ok(buf)
+}
+
+/// Decodes the CBOR data into a JSON value.
+pub fn cbor_to_value(cbor: &[u8]) -> Result<Value> {
+    let mut decoder = Decoder::new(cbor);
+    let value = decode_value(&mut decoder, 0)?;
+    if decoder.position() != cbor.len() {
+        return Err(Error::CodecError {
+            msg: format!("trailing bytes at {}", decoder.position()),
+        });
+    }
+    Ok(value)
+}
+
+/// Returns the bytes if the string is written as `h'<hex>'`.
+fn parse_bytes(s: &str) -> Option<Vec<u8>> {
+    let hex_str = s.strip_prefix("h'")?.strip_suffix('\'')?;
+    hex::decode(hex_str).ok()
+}
+
+fn encode_value(e: &mut Encoder<&mut Vec<u8>>, value: &Value) -> Result<()> {
+    match value {
+        Value::Null => {
+            e.null().map_err(codec_error)?;
+        }
+        Value::Bool(b) => {
+            e.bool(*b).map_err(codec_error)?;
+        }
+        Value::Number(n) => {
+            if let Some(u) = n.as_u64() {
+                e.u64(u).map_err(codec_error)?;
+            } else if let Some(i) = n.as_i64() {
+                e.i64(i).map_err(codec_error)?;
+            } else {
+                let f = n.as_f64().ok_or_else(|| codec_error("invalid number"))?;
+                e.f64(f).map_err(codec_error)?;
+            }
+        }
+        Value::String(s) => match parse_bytes(s) {
+            Some(bytes) => {
+                e.bytes(&bytes).map_err(codec_error)?;
+            }
+            None => {
+                e.str(s).map_err(codec_error)?;
+            }
+        },
+        Value::Array(items) => {
+            e.array(items.len() as u64).map_err(codec_error)?;
+            for item in items {
+                encode_value(e, item)?;
+            }
+        }
+        Value::Object(entries) => {
+            e.map(entries.len() as u64).map_err(codec_error)?;
+            for (key, item) in entries {
+                match key.parse::<u64>() {
+                    Ok(index) => {
+                        e.u64(index).map_err(codec_error)?;
+                    }
+                    Err(_) => {
+                        e.str(key).map_err(codec_error)?;
+                    }
+                }
+                encode_value(e, item)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads the items of an array or a map. `None` length means an indefinite length.
+fn decode_items<F>(d: &mut Decoder, len: Option<u64>, mut f: F) -> Result<()>
+where
+    F: FnMut(&mut Decoder) -> Result<()>,
+{
+    match len {
+        Some(len) => {
+            for _ in 0..len {
+                f(d)?;
+            }
+        }
+        None => {
+            while d.datatype().map_err(codec_error)? != Type::Break {
+                f(d)?;
+            }
+            // Skipping the break byte
+            d.set_position(d.position() + 1);
+        }
+    }
+    Ok(())
+}
+
+fn decode_key(d: &mut Decoder, depth: usize) -> Result<String> {
+    match decode_value(d, depth)? {
+        Value::String(s) => Ok(s),
+        Value::Number(n) => Ok(n.to_string()),
+        other => Err(Error::CodecError {
+            msg: format!("unsupported map key: {other}"),
+        }),
+    }
+}
+
+fn decode_value(d: &mut Decoder, depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        return Err(Error::CodecError {
+            msg: format!("nesting is deeper than {MAX_DEPTH}"),
+        });
+    }
+
+    let value = match d.datatype().map_err(codec_error)? {
+        Type::Null | Type::Undefined => {
+            d.skip().map_err(codec_error)?;
+            Value::Null
+        }
+        Type::Bool => Value::Bool(d.bool().map_err(codec_error)?),
+        Type::U8 | Type::U16 | Type::U32 | Type::U64 => Value::from(d.u64().map_err(codec_error)?),
+        Type::I8 | Type::I16 | Type::I32 | Type::I64 => Value::from(d.i64().map_err(codec_error)?),
+        Type::F32 => float_value(d.f32().map_err(codec_error)? as f64)?,
+        Type::F64 => float_value(d.f64().map_err(codec_error)?)?,
+        Type::Bytes | Type::BytesIndef => {
+            let mut bytes = Vec::new();
+            for chunk in d.bytes_iter().map_err(codec_error)? {
+                bytes.extend_from_slice(chunk.map_err(codec_error)?);
+            }
+            Value::String(format!("h'{}'", hex::encode(bytes)))
+        }
+        Type::String | Type::StringIndef => {
+            let mut s = String::new();
+            for chunk in d.str_iter().map_err(codec_error)? {
+                s.push_str(chunk.map_err(codec_error)?);
+            }
+            Value::String(s)
+        }
+        Type::Array | Type::ArrayIndef => {
+            let len = d.array().map_err(codec_error)?;
+            let mut items = Vec::new();
+            decode_items(d, len, |d| {
+                items.push(decode_value(d, depth + 1)?);
+                Ok(())
+            })?;
+            Value::Array(items)
+        }
+        Type::Map | Type::MapIndef => {
+            let len = d.map().map_err(codec_error)?;
+            let mut entries = Map::new();
+            decode_items(d, len, |d| {
+                let key = decode_key(d, depth + 1)?;
+                entries.insert(key, decode_value(d, depth + 1)?);
+                Ok(())
+            })?;
+            Value::Object(entries)
+        }
+        Type::Tag => {
+            // Tags are ignored and the tagged value is decoded.
+            d.tag().map_err(codec_error)?;
+            decode_value(d, depth + 1)?
+        }
+        other => {
+            return Err(Error::CodecError {
+                msg: format!("unsupported data type: {other:?}"),
+            })
+        }
+    };
+    Ok(value)
+}
+
+fn float_value(f: f64) -> Result<Value> {
+    Number::from_f64(f)
+        .map(Value::Number)
+        .ok_or_else(|| codec_error(format!("unsupported float: {f}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_contract::message::{ProcMsg, QueryMsg};
+
+    #[test]
+    fn test_round_trip() {
+        let json = r#"[1,-2,"text","h'0102'",{"0":true,"name":null},1.5]"#;
+        let cbor = json_to_cbor(json).unwrap();
+        assert_eq!(cbor_to_json(&cbor).unwrap(), json);
+    }
+
+    #[test]
+    fn test_key_order() {
+        let json = r#"{"name":"tanour","age":1,"0":true}"#;
+        let cbor = json_to_cbor(json).unwrap();
+        assert_eq!(cbor_to_json(&cbor).unwrap(), json);
+    }
+
+    #[test]
+    fn test_contract_messages() {
+        let msg = ProcMsg::SetMessage {
+            msg: "hello world!".to_string(),
+        };
+        let encoded = minicbor::to_vec(msg).unwrap();
+        let json = cbor_to_json(&encoded).unwrap();
+        assert_eq!(json_to_cbor(&json).unwrap(), encoded);
+
+        let msg = QueryMsg::Hasher {
+            data: vec![1, 2, 3],
+        };
+        let encoded = minicbor::to_vec(msg).unwrap();
+        let json = cbor_to_json(&encoded).unwrap();
+        assert_eq!(json_to_cbor(&json).unwrap(), encoded);
+    }
+
+    #[test]
+    fn test_invalid_json() {
+        assert!(matches!(json_to_cbor("[1,"), Err(Error::CodecError { .. })));
+    }
+
+    #[test]
+    fn test_nesting_depth() {
+        // Nested arrays with one item, ending with zero
+        let mut cbor = vec![0x81; MAX_DEPTH];
+        cbor.push(0x00);
+        assert!(cbor_to_json(&cbor).is_ok());
+
+        let mut cbor = vec![0x81; 100_000];
+        cbor.push(0x00);
+        assert!(matches!(cbor_to_json(&cbor), Err(Error::CodecError { .. })));
+
+        // Nested tags
+        let mut cbor = vec![0xc1; 100_000];
+        cbor.push(0x00);
+        assert!(matches!(cbor_to_json(&cbor), Err(Error::CodecError { .. })));
+    }
+
+    #[test]
+    fn test_trailing_bytes() {
+        assert!(matches!(
+            cbor_to_json(&[0x01, 0x02]),
+            Err(Error::CodecError { .. })
+        ));
+    }
+}
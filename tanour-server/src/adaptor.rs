@@ -1,73 +1,226 @@
+use crate::metrics::Metrics;
 use crate::tanour_capnp;
 
-use log::debug;
+use std::time::Instant;
+use tokio::sync::{mpsc, oneshot};
+use tracing::Instrument;
 
-use tanour::{blockchain_api::BlockchainAPI, Address};
+use tanour::{blockchain_api::BlockchainAPI, error::Error, Address};
 
-unsafe impl Send for tanour_capnp::provider::Client {}
+type Reply<T> = oneshot::Sender<Result<T, capnp::Error>>;
 
-pub struct BlockchainAdaptor {
+/// The provider calls that the workers send to the RPC thread.
+/// The Cap'n Proto clients can't leave the RPC thread, so the calls are bridged through the channels.
+pub enum ProviderRequest {
+    PageSize(Reply<u32>),
+    StorageSize(Reply<u32>),
+    ReadPage(u32, Reply<Vec<u8>>),
+    ReadPages(Vec<u32>, Reply<Vec<Vec<u8>>>),
+    WritePage(u32, Vec<u8>, Reply<()>),
+    WritePages(Vec<(u32, Vec<u8>)>, Reply<()>),
+    Exist(Address, Reply<bool>),
+}
+
+impl ProviderRequest {
+    fn method(&self) -> &'static str {
+        match self {
+            ProviderRequest::PageSize(_) => "page_size",
+            ProviderRequest::StorageSize(_) => "storage_size",
+            ProviderRequest::ReadPage(..) => "read_page",
+            ProviderRequest::ReadPages(..) => "read_pages",
+            ProviderRequest::WritePage(..) => "write_page",
+            ProviderRequest::WritePages(..) => "write_pages",
+            ProviderRequest::Exist(..) => "exists",
+        }
+    }
+}
+
+/// Serves the provider requests on the RPC thread, until all the adaptors are dropped.
+pub async fn serve_provider(
     client: tanour_capnp::provider::Client,
+    mut requests: mpsc::UnboundedReceiver<ProviderRequest>,
+    metrics: &Metrics,
+) {
+    while let Some(request) = requests.recv().await {
+        let method = request.method();
+        let span = tracing::debug_span!("provider_rpc", method);
+        let started = Instant::now();
+        let failed = handle_request(&client, request).instrument(span).await;
+        metrics.observe_provider_call(method, started.elapsed(), failed);
+    }
 }
 
-impl BlockchainAdaptor {
-    pub fn new(client: tanour_capnp::provider::Client) -> Self {
-        BlockchainAdaptor { client }
+/// Calls the provider and replies to the worker. It returns true if the call is failed.
+async fn handle_request(client: &tanour_capnp::provider::Client, request: ProviderRequest) -> bool {
+    // The replies are dropped if the worker is gone.
+    match request {
+        ProviderRequest::PageSize(reply) => reply_with(reply, page_size(client).await),
+        ProviderRequest::StorageSize(reply) => reply_with(reply, storage_size(client).await),
+        ProviderRequest::ReadPage(page_no, reply) => {
+            reply_with(reply, read_page(client, page_no).await)
+        }
+        ProviderRequest::ReadPages(page_nos, reply) => {
+            reply_with(reply, read_pages(client, &page_nos).await)
+        }
+        ProviderRequest::WritePage(page_no, data, reply) => {
+            reply_with(reply, write_page(client, page_no, &data).await)
+        }
+        ProviderRequest::WritePages(pages, reply) => {
+            reply_with(reply, write_pages(client, &pages).await)
+        }
+        ProviderRequest::Exist(address, reply) => reply_with(reply, exists(client, &address).await),
     }
 }
 
-impl BlockchainAPI for BlockchainAdaptor {
-    fn page_size(&self) -> Result<u32, tanour::error::Error> {
-        let req = self.client.page_size_request();
+/// Sends the result to the worker and returns true if the call is failed.
+fn reply_with<T>(reply: Reply<T>, result: Result<T, capnp::Error>) -> bool {
+    let failed = result.is_err();
+    if let Err(err) = &result {
+        tracing::warn!("provider call is failed: {err}");
+    }
+    let _ = reply.send(result);
+    failed
+}
 
-        let handle = async move {
-            debug!("Try ot call `page_size` method in client");
-            let result = req.send().promise.await.unwrap(); //TODO: no unwrap
-            result.get().unwrap().get_size() //TODO: no unwrap
-        };
+async fn page_size(client: &tanour_capnp::provider::Client) -> Result<u32, capnp::Error> {
+    let req = client.page_size_request();
+    let result = req.send().promise.await?;
+    Ok(result.get()?.get_size())
+}
+
+async fn storage_size(client: &tanour_capnp::provider::Client) -> Result<u32, capnp::Error> {
+    let req = client.storage_size_request();
+    let result = req.send().promise.await?;
+    Ok(result.get()?.get_size())
+}
 
-        Ok(futures::executor::block_on(handle))
+async fn read_page(
+    client: &tanour_capnp::provider::Client,
+    page_no: u32,
+) -> Result<Vec<u8>, capnp::Error> {
+    let mut req = client.read_page_request();
+    req.get().set_page_no(page_no);
+    let result = req.send().promise.await?;
+    Ok(result.get()?.get_data()?.to_vec())
+}
+
+async fn read_pages(
+    client: &tanour_capnp::provider::Client,
+    page_nos: &[u32],
+) -> Result<Vec<Vec<u8>>, capnp::Error> {
+    let mut req = client.read_pages_request();
+    let mut list = req.get().init_page_nos(page_nos.len() as u32);
+    for (i, page_no) in page_nos.iter().enumerate() {
+        list.set(i as u32, *page_no);
     }
 
-    fn read_page(&self, page_no: u32) -> Result<Vec<u8>, tanour::error::Error> {
-        let mut req = self.client.read_page_request();
-        req.get().set_page_no(page_no);
+    let result = req.send().promise.await?;
+    let mut pages = Vec::new();
+    for page in result.get()?.get_pages()?.iter() {
+        pages.push(page?.to_vec());
+    }
+    Ok(pages)
+}
 
-        let handle = async move {
-            debug!("Try ot call `read_page` method in client");
-            let result = req.send().promise.await.unwrap(); //TODO: no unwrap
-            result.get().unwrap().get_data().unwrap().to_vec() //TODO: no unwrap
-        };
+async fn write_page(
+    client: &tanour_capnp::provider::Client,
+    page_no: u32,
+    data: &[u8],
+) -> Result<(), capnp::Error> {
+    let mut req = client.write_page_request();
+    req.get().set_page_no(page_no);
+    req.get().set_data(data);
+    let result = req.send().promise.await?;
+    result.get()?;
+    Ok(())
+}
 
-        Ok(futures::executor::block_on(handle)) //TODO: no unwrap
+async fn write_pages(
+    client: &tanour_capnp::provider::Client,
+    pages: &[(u32, Vec<u8>)],
+) -> Result<(), capnp::Error> {
+    let mut req = client.write_pages_request();
+    let mut list = req.get().init_pages(pages.len() as u32);
+    for (i, (page_no, data)) in pages.iter().enumerate() {
+        let mut page = list.reborrow().get(i as u32);
+        page.set_page_no(*page_no);
+        page.set_data(data);
     }
 
-    fn write_page(&self, page_no: u32, data: &[u8]) -> Result<(), tanour::error::Error> {
-        let mut req = self.client.write_page_request();
-        req.get().set_page_no(page_no);
-        req.get().set_data(data);
+    let result = req.send().promise.await?;
+    result.get()?;
+    Ok(())
+}
+
+async fn exists(
+    client: &tanour_capnp::provider::Client,
+    address: &Address,
+) -> Result<bool, capnp::Error> {
+    let mut req = client.exists_request();
+    req.get().set_address(address);
+    let result = req.send().promise.await?;
+    Ok(result.get()?.get_exist())
+}
+
+/// The blockchain API for the contracts running on the workers.
+/// Each call is sent to the RPC thread and the worker is blocked until the reply.
+pub struct BlockchainAdaptor {
+    requests: mpsc::UnboundedSender<ProviderRequest>,
+}
+
+impl BlockchainAdaptor {
+    pub fn new(requests: mpsc::UnboundedSender<ProviderRequest>) -> Self {
+        BlockchainAdaptor { requests }
+    }
 
-        let handle = async move {
-            debug!("Try ot call `write_page` method in client");
-            let result = req.send().promise.await.unwrap(); //TODO: no unwrap
-            result.get().unwrap(); //TODO: no unwrap
+    fn call<T>(&self, request: impl FnOnce(Reply<T>) -> ProviderRequest) -> Result<T, Error> {
+        let disconnected = || Error::ProviderError {
+            msg: "provider is disconnected".to_string(),
         };
 
-        futures::executor::block_on(handle);
-        Ok(()) //TODO: no unwrap
+        let (tx, rx) = oneshot::channel();
+        self.requests
+            .send(request(tx))
+            .map_err(|_| disconnected())?;
+        rx.blocking_recv()
+            .map_err(|_| disconnected())?
+            .map_err(provider_error)
     }
+}
 
-    fn exist(&self, address: &Address) -> Result<bool, tanour::error::Error> {
-        let mut req = self.client.exists_request();
-        req.get().set_address(address);
+fn provider_error(original: capnp::Error) -> Error {
+    Error::ProviderError {
+        msg: format!("{original}"),
+    }
+}
 
-        let handle = async move {
-            debug!("Try ot call `exists` method in client");
-            let result = req.send().promise.await.unwrap(); //TODO: no unwrap
-            result.get().unwrap().get_exist() //TODO: no unwrap
-        };
+impl BlockchainAPI for BlockchainAdaptor {
+    fn page_size(&self) -> Result<u32, Error> {
+        self.call(ProviderRequest::PageSize)
+    }
+
+    fn storage_size(&self) -> Result<u32, Error> {
+        self.call(ProviderRequest::StorageSize)
+    }
+
+    fn read_page(&self, page_no: u32) -> Result<Vec<u8>, Error> {
+        self.call(|reply| ProviderRequest::ReadPage(page_no, reply))
+    }
+
+    fn read_pages(&self, page_nos: &[u32]) -> Result<Vec<Vec<u8>>, Error> {
+        self.call(|reply| ProviderRequest::ReadPages(page_nos.to_vec(), reply))
+    }
+
+    fn write_page(&self, page_no: u32, data: &[u8]) -> Result<(), Error> {
+        self.call(|reply| ProviderRequest::WritePage(page_no, data.to_vec(), reply))
+    }
+
+    fn write_pages(&self, pages: &[(u32, Vec<u8>)]) -> Result<(), Error> {
+        self.call(|reply| ProviderRequest::WritePages(pages.to_vec(), reply))
+    }
 
-        Ok(futures::executor::block_on(handle))
+    fn exist(&self, address: &Address) -> Result<bool, Error> {
+        self.call(|reply| ProviderRequest::Exist(*address, reply))
     }
 
     fn current_block_number(&self) -> u32 {
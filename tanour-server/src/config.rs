@@ -0,0 +1,326 @@
+use clap::Parser;
+use serde::Deserialize;
+use std::path::PathBuf;
+use tanour::gas::GasSchedule;
+
+/// The server configuration.
+/// It is loaded from the TOML file and then overridden by the flags and the environment variables.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// The address to listen on, as `HOST:PORT`. It is empty to not listen on TCP.
+    pub listen: String,
+    /// The path of the Unix domain socket to listen on, in addition to the TCP address
+    pub socket: Option<PathBuf>,
+    /// The file permissions of the Unix domain socket
+    pub socket_mode: u32,
+    /// The address of the HTTP metrics endpoint, as `HOST:PORT`. It is empty to disable the metrics.
+    pub metrics_listen: String,
+    /// The log level: off, error, warn, info, debug or trace.
+    /// It can also be a filter per module, like `info,tanour=debug`.
+    pub log_level: String,
+    pub executor: ExecutorConfig,
+    pub cache: CacheConfig,
+    pub limits: LimitsConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExecutorConfig {
+    /// The maximum memory of the contracts in Wasm pages (64 KiB each)
+    pub memory_limit_page: u32,
+    /// The maximum metering points that an execution can consume
+    pub metering_limit: u64,
+    /// The maximum stack height, in the values that the function frames keep
+    pub stack_height_limit: u32,
+    /// The maximum storage size of the contracts in bytes
+    pub storage_limit: u32,
+    pub gas_schedule: GasSchedule,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    /// The number of compiled modules that are kept in memory
+    pub size: usize,
+    /// The directory to keep the compiled modules, they are only kept in memory if it is not set
+    pub dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// The maximum number of the concurrent connections
+    pub max_connections: usize,
+    /// The maximum number of the concurrent executions, it is also the number of the workers
+    pub max_executions: usize,
+    /// The seconds to wait for the running executions on shutdown
+    pub shutdown_timeout: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: "127.0.0.1:32145".to_string(),
+            socket: None,
+            socket_mode: 0o660,
+            metrics_listen: String::new(),
+            log_level: "info".to_string(),
+            executor: ExecutorConfig::default(),
+            cache: CacheConfig::default(),
+            limits: LimitsConfig::default(),
+        }
+    }
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        ExecutorConfig {
+            memory_limit_page: 1000,
+            metering_limit: 11100,
+            stack_height_limit: 32768,
+            storage_limit: 1024 * 1024,
+            gas_schedule: GasSchedule::default(),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            size: 100,
+            dir: None,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_connections: 64,
+            max_executions: 16,
+            shutdown_timeout: 30,
+        }
+    }
+}
+
+/// Runs tanour as a server.
+#[derive(Debug, Parser)]
+#[command(name = "tanour", version)]
+pub struct Args {
+    /// The address to listen on, as `HOST:PORT`
+    #[arg(env = "TANOUR_LISTEN")]
+    listen: Option<String>,
+
+    /// The path of the Unix domain socket to listen on
+    #[arg(long, env = "TANOUR_SOCKET")]
+    socket: Option<PathBuf>,
+
+    /// The file permissions of the Unix domain socket in octal, like 660
+    #[arg(long, env = "TANOUR_SOCKET_MODE", value_parser = parse_mode)]
+    socket_mode: Option<u32>,
+
+    /// The address of the HTTP metrics endpoint, as `HOST:PORT`
+    #[arg(long, env = "TANOUR_METRICS_LISTEN")]
+    metrics_listen: Option<String>,
+
+    /// Path to the TOML configuration file
+    #[arg(long, env = "TANOUR_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// The log level: off, error, warn, info, debug or trace
+    #[arg(long, env = "TANOUR_LOG_LEVEL")]
+    log_level: Option<String>,
+
+    /// The maximum memory of the contracts in Wasm pages (64 KiB each)
+    #[arg(long, env = "TANOUR_MEMORY_LIMIT_PAGE")]
+    memory_limit_page: Option<u32>,
+
+    /// The maximum metering points that an execution can consume
+    #[arg(long, env = "TANOUR_METERING_LIMIT")]
+    metering_limit: Option<u64>,
+
+    /// The number of compiled modules that are kept in memory
+    #[arg(long, env = "TANOUR_CACHE_SIZE")]
+    cache_size: Option<usize>,
+
+    /// The directory to keep the compiled modules
+    #[arg(long, env = "TANOUR_CACHE_DIR")]
+    cache_dir: Option<PathBuf>,
+
+    /// The maximum number of the concurrent connections
+    #[arg(long, env = "TANOUR_MAX_CONNECTIONS")]
+    max_connections: Option<usize>,
+
+    /// The maximum number of the concurrent executions
+    #[arg(long, env = "TANOUR_MAX_EXECUTIONS")]
+    max_executions: Option<usize>,
+
+    /// The seconds to wait for the running executions on shutdown
+    #[arg(long, env = "TANOUR_SHUTDOWN_TIMEOUT")]
+    shutdown_timeout: Option<u64>,
+}
+
+fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 8).map_err(|err| format!("invalid octal mode {s}: {err}"))
+}
+
+impl Config {
+    /// Loads the configuration file, if it is given, and applies the overrides.
+    pub fn load(args: Args) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = match &args.config {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .map_err(|err| format!("unable to read {path:?}: {err}"))?;
+                toml::from_str(&text).map_err(|err| format!("invalid config {path:?}: {err}"))?
+            }
+            None => Config::default(),
+        };
+
+        if let Some(listen) = args.listen {
+            config.listen = listen;
+        }
+        if let Some(socket) = args.socket {
+            config.socket = Some(socket);
+        }
+        if let Some(socket_mode) = args.socket_mode {
+            config.socket_mode = socket_mode;
+        }
+        if let Some(metrics_listen) = args.metrics_listen {
+            config.metrics_listen = metrics_listen;
+        }
+        if let Some(log_level) = args.log_level {
+            config.log_level = log_level;
+        }
+        if let Some(memory_limit_page) = args.memory_limit_page {
+            config.executor.memory_limit_page = memory_limit_page;
+        }
+        if let Some(metering_limit) = args.metering_limit {
+            config.executor.metering_limit = metering_limit;
+        }
+        if let Some(cache_size) = args.cache_size {
+            config.cache.size = cache_size;
+        }
+        if let Some(cache_dir) = args.cache_dir {
+            config.cache.dir = Some(cache_dir);
+        }
+        if let Some(max_connections) = args.max_connections {
+            config.limits.max_connections = max_connections;
+        }
+        if let Some(max_executions) = args.max_executions {
+            config.limits.max_executions = max_executions;
+        }
+        if let Some(shutdown_timeout) = args.shutdown_timeout {
+            config.limits.shutdown_timeout = shutdown_timeout;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the values that would stop the server from serving.
+    fn validate(&self) -> Result<(), String> {
+        if self.listen.is_empty() && self.socket.is_none() {
+            return Err("no address or socket to listen on".to_string());
+        }
+        let limits = [
+            (
+                "executor.memory_limit_page",
+                self.executor.memory_limit_page as u64,
+            ),
+            ("executor.metering_limit", self.executor.metering_limit),
+            (
+                "executor.stack_height_limit",
+                self.executor.stack_height_limit as u64,
+            ),
+            ("executor.storage_limit", self.executor.storage_limit as u64),
+            ("cache.size", self.cache.size as u64),
+            ("limits.max_connections", self.limits.max_connections as u64),
+            ("limits.max_executions", self.limits.max_executions as u64),
+        ];
+        for (name, value) in limits {
+            if value == 0 {
+                return Err(format!(
+                    "invalid config: {name} should be greater than zero"
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_config(name: &str, text: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("tanour-config-{}-{name}.toml", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn load(args: &[&str]) -> Result<Config, Box<dyn std::error::Error>> {
+        let args = Args::try_parse_from(std::iter::once("tanour").chain(args.iter().copied()))?;
+        Config::load(args)
+    }
+
+    #[test]
+    fn test_default() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.executor.metering_limit, 11100);
+        assert_eq!(config.limits.max_executions, 16);
+    }
+
+    #[test]
+    fn test_precedence() {
+        let text = r#"
+[executor]
+metering_limit = 5000
+memory_limit_page = 10
+
+[limits]
+shutdown_timeout = 5
+"#;
+        let path = write_config("precedence", text);
+        std::env::set_var("TANOUR_SHUTDOWN_TIMEOUT", "7");
+        std::env::set_var("TANOUR_MEMORY_LIMIT_PAGE", "20");
+        let config = load(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--memory-limit-page",
+            "30",
+        ]);
+        std::env::remove_var("TANOUR_SHUTDOWN_TIMEOUT");
+        std::env::remove_var("TANOUR_MEMORY_LIMIT_PAGE");
+        std::fs::remove_file(path).unwrap();
+        let config = config.unwrap();
+
+        // The file overrides the defaults
+        assert_eq!(config.executor.metering_limit, 5000);
+        // The environment variables override the file
+        assert_eq!(config.limits.shutdown_timeout, 7);
+        // The flags override the environment variables
+        assert_eq!(config.executor.memory_limit_page, 30);
+        // The other values are the defaults
+        assert_eq!(config.executor.stack_height_limit, 32768);
+    }
+
+    #[test]
+    fn test_zero_limits() {
+        assert!(load(&["--max-executions", "0"]).is_err());
+        assert!(load(&["--max-connections", "0"]).is_err());
+        assert!(load(&["--metering-limit", "0"]).is_err());
+        assert!(load(&["--cache-size", "0"]).is_err());
+
+        let path = write_config("zero", "[executor]\nstack_height_limit = 0\n");
+        let res = load(&["--config", path.to_str().unwrap()]);
+        std::fs::remove_file(path).unwrap();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn test_no_listener() {
+        assert!(load(&[""]).is_err());
+    }
+}
@@ -1,79 +1,413 @@
-use crate::adaptor::BlockchainAdaptor;
+use crate::adaptor::{serve_provider, BlockchainAdaptor};
+use crate::config::ExecutorConfig;
+use crate::metrics::Metrics;
 use crate::tanour_capnp;
 use crate::tanour_capnp::executor;
+use crate::worker_pool::WorkerPool;
 use capnp::capability::Promise;
 use capnp::Error;
 use capnp_rpc::pry;
-use tanour::address_from_bytes;
-use tanour::contract::Params;
-use tokio::sync::oneshot;
-use tokio::sync::oneshot::error::TryRecvError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tanour::abi::Abi;
+use tanour::cache::ModuleCache;
+use tanour::contract::{CompileInfo, Contract, Params, StorageDiff};
+use tanour::{address_from_bytes, address_to_hex, Address, ADDRESS_SIZE};
+use tokio::sync::{mpsc, Semaphore};
+use tracing::Instrument;
 
-pub struct ExecutorImpl;
+/// The state that the executors of all the connections share.
+pub struct ExecutorContext {
+    pub config: ExecutorConfig,
+    pub module_cache: Arc<ModuleCache>,
+    // Limits the number of the concurrent executions,
+    // a permit is held until the execution is finished on the worker
+    pub executions: Arc<Semaphore>,
+    pub workers: WorkerPool,
+    pub metrics: Arc<Metrics>,
+    // New executions are rejected when the server is shutting down
+    pub shutting_down: AtomicBool,
+}
+
+pub struct ExecutorImpl {
+    context: Arc<ExecutorContext>,
+}
+
+impl ExecutorImpl {
+    pub fn new(context: Arc<ExecutorContext>) -> Self {
+        ExecutorImpl { context }
+    }
+}
+
+/// The outcome of executing a transaction.
+/// Contract failures are part of the outcome and they are reported back to the client.
+struct ExecutionResult {
+    gas_left: u64,
+    gas_used: u64,
+    result: tanour::error::Result<Vec<u8>>,
+    // The address of the deployed contract
+    contract: Option<Address>,
+    // The initial storage pages of the deployed contract
+    pages: Vec<(u32, Vec<u8>)>,
+    // The number of bytes that the storage has grown
+    storage_growth: u32,
+    // The changed byte ranges of the storage
+    diffs: Vec<StorageDiff>,
+    // It is not set if the code is not compiled
+    compile_info: Option<CompileInfo>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Instantiate,
+    Process,
+    Query,
+    Deploy,
+}
+
+impl Action {
+    fn name(&self) -> &'static str {
+        match self {
+            Action::Instantiate => "instantiate",
+            Action::Process => "process",
+            Action::Query => "query",
+            Action::Deploy => "deploy",
+        }
+    }
+}
+
+/// The transaction data, read from the request on the RPC thread to be executed on a worker.
+struct TransactionData {
+    id: Vec<u8>,
+    action: Action,
+    // The deployer of the contract, only for the deploy action
+    sender: Address,
+    // The contract address, except for the deploy action
+    address: Address,
+    // The gas that the transaction is given, capped by the metering limit
+    gas: u64,
+    salt: Vec<u8>,
+    code: Vec<u8>,
+    args: Vec<u8>,
+}
+
+fn read_address(data: &[u8], field: &str) -> Result<Address, Error> {
+    if data.len() != ADDRESS_SIZE {
+        return Err(Error::failed(format!(
+            "invalid {field} length: {}, expected {ADDRESS_SIZE}",
+            data.len()
+        )));
+    }
+    Ok(address_from_bytes(data))
+}
+
+fn read_transaction(
+    transaction: tanour_capnp::transaction::Reader,
+) -> Result<TransactionData, Error> {
+    let action = match transaction.get_action().which()? {
+        tanour_capnp::transaction::action::Instantiate(_) => Action::Instantiate,
+        tanour_capnp::transaction::action::Process(_) => Action::Process,
+        tanour_capnp::transaction::action::Query(_) => Action::Query,
+        tanour_capnp::transaction::action::Deploy(_) => Action::Deploy,
+    };
+
+    let mut data = TransactionData {
+        id: transaction.get_id()?.to_vec(),
+        action,
+        sender: [0; ADDRESS_SIZE],
+        address: [0; ADDRESS_SIZE],
+        gas: transaction.get_gas(),
+        salt: Vec::new(),
+        code: transaction.get_code()?.to_vec(),
+        args: transaction.get_args()?.to_vec(),
+    };
+    match action {
+        Action::Deploy => {
+            data.sender = read_address(transaction.get_sender()?, "sender")?;
+            data.salt = transaction.get_salt()?.to_vec();
+        }
+        _ => {
+            data.address = read_address(transaction.get_address()?, "address")?;
+        }
+    }
+    Ok(data)
+}
+
+/// Executes the transaction, it runs on a worker.
+fn execute_transaction(
+    transaction: TransactionData,
+    adaptor: BlockchainAdaptor,
+    config: &ExecutorConfig,
+    module_cache: Arc<ModuleCache>,
+) -> ExecutionResult {
+    let msg = &transaction.args;
+    let code = &transaction.code;
+    let params = Params {
+        memory_limit_page: config.memory_limit_page,
+        metering_limit: config.metering_limit,
+        stack_height_limit: config.stack_height_limit,
+        storage_limit: config.storage_limit,
+        gas_schedule: config.gas_schedule,
+        module_cache: Some(module_cache),
+        trace: None,
+        profiling: false,
+    };
+    // The metering limit is compiled into the module, so the transaction gas only lowers it.
+    let gas_limit = transaction.gas.min(params.metering_limit);
+    let action = transaction.action;
+
+    let contract = match action {
+        Action::Deploy => Contract::deploy(
+            Box::new(adaptor),
+            &transaction.sender,
+            &transaction.salt,
+            code,
+            params,
+        ),
+        _ => Contract::new(Box::new(adaptor), &transaction.address, code, params),
+    };
+
+    let contract = contract.and_then(|contract| {
+        contract.set_gas_limit(gas_limit)?;
+        Ok(contract)
+    });
+    let mut contract = match contract {
+        Ok(contract) => {
+            // The address of the deployed contract is known after deriving it
+            tracing::Span::current().record("address", address_to_hex(contract.address()));
+            contract
+        }
+        Err(err) => {
+            return ExecutionResult {
+                gas_left: gas_limit,
+                gas_used: 0,
+                result: Err(err),
+                contract: None,
+                pages: Vec::new(),
+                storage_growth: 0,
+                diffs: Vec::new(),
+                compile_info: None,
+            }
+        }
+    };
+
+    let mut deployed = None;
+    let result = match action {
+        Action::Instantiate => contract.call_instantiate(msg),
+        Action::Process => contract.call_process(msg),
+        Action::Query => contract.call_query(msg),
+        Action::Deploy => {
+            deployed = Some(*contract.address());
+            contract.call_instantiate(msg)
+        }
+    };
+
+    let pages = match (&deployed, &result) {
+        (Some(_), Ok(_)) => contract.updated_pages().unwrap_or_default(),
+        _ => Vec::new(),
+    };
+
+    let diffs = match &result {
+        Ok(_) => contract.storage_diffs().unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+
+    // Updated pages are written back in one batch, except for the queries.
+    let result = match (action, result) {
+        (Action::Query, result) => result,
+        (_, Ok(data)) => contract.commit().map(|_| data),
+        (_, Err(err)) => Err(err),
+    };
+
+    let storage_growth = match &result {
+        Ok(_) => contract.storage_growth().unwrap_or(0),
+        Err(_) => 0,
+    };
+    let gas_left = contract.remaining_points().unwrap_or(0);
+
+    ExecutionResult {
+        gas_left,
+        gas_used: gas_limit - gas_left,
+        result,
+        contract: deployed,
+        pages,
+        storage_growth,
+        diffs,
+        compile_info: Some(contract.compile_info()),
+    }
+}
+
+fn observe_execution(
+    metrics: &Metrics,
+    action: Action,
+    execution_result: &ExecutionResult,
+    duration: Duration,
+) {
+    if let Some(info) = &execution_result.compile_info {
+        metrics.observe_compile(info);
+    }
+    let (status, error_code) = match &execution_result.result {
+        Ok(_) => ("success", None),
+        Err(tanour::error::Error::OutOfGas { .. }) => ("out_of_gas", None),
+        Err(err) => ("error", Some(err.code())),
+    };
+    tracing::info!(
+        status,
+        gas_used = execution_result.gas_used,
+        ?duration,
+        "execution is finished"
+    );
+    metrics.observe_execution(
+        action.name(),
+        status,
+        error_code,
+        execution_result.gas_used,
+        duration,
+    );
+}
+
+/// Fills the result data of the response.
+fn set_result_data(
+    mut builder: tanour_capnp::result_data::Builder,
+    execution_result: ExecutionResult,
+) {
+    builder.set_gas_left(execution_result.gas_left);
+    builder.set_gas_used(execution_result.gas_used);
+    builder.set_storage_growth(execution_result.storage_growth);
+
+    if let Some(address) = execution_result.contract {
+        builder.set_contract(&address);
+    }
+
+    let mut pages = builder
+        .reborrow()
+        .init_pages(execution_result.pages.len() as u32);
+    for (i, (page_no, data)) in execution_result.pages.iter().enumerate() {
+        let mut page = pages.reborrow().get(i as u32);
+        page.set_page_no(*page_no);
+        page.set_data(data);
+    }
+
+    let mut diffs = builder
+        .reborrow()
+        .init_diffs(execution_result.diffs.len() as u32);
+    for (i, diff) in execution_result.diffs.iter().enumerate() {
+        let mut item = diffs.reborrow().get(i as u32);
+        item.set_offset(diff.offset);
+        item.set_old_data(&diff.old_data);
+        item.set_new_data(&diff.new_data);
+    }
+
+    match execution_result.result {
+        Ok(data) => {
+            builder.set_data(&data);
+            builder.init_status().set_success(());
+        }
+        Err(tanour::error::Error::OutOfGas { .. }) => {
+            builder.init_status().set_out_of_gas(());
+        }
+        Err(err) => {
+            let mut error = builder.init_status().init_error();
+            error.set_code(err.code());
+            error.set_message(err.to_string().as_str());
+            let mut frames = error.init_frames(err.frames().len() as u32);
+            for (i, frame) in err.frames().iter().enumerate() {
+                let mut item = frames.reborrow().get(i as u32);
+                item.set_func_index(frame.func_index);
+                item.set_func_name(frame.func_name.as_deref().unwrap_or_default());
+                item.set_module_offset(frame.module_offset as u64);
+                item.set_func_offset(frame.func_offset as u64);
+            }
+        }
+    }
+}
 
-// TODO: ??? why ???
-#[allow(clippy::async_yields_async)]
 impl executor::Server for ExecutorImpl {
+    /// Executes the transaction on a worker.
+    ///
+    /// If the client disconnects or cancels the call, the returned promise is dropped.
+    /// Then the execution is skipped if it is still queued,
+    /// otherwise it fails on the next provider call.
     fn execute(
         &mut self,
         params: executor::ExecuteParams,
         mut results: executor::ExecuteResults,
     ) -> Promise<(), Error> {
-        let (tx, mut rx) = oneshot::channel();
-
-        tokio::task::spawn_local(async move {
-            let provider_client = pry!(pry!(params.get()).get_provider());
-            let transaction = pry!(pry!(params.get()).get_transaction());
-            let adaptor = BlockchainAdaptor::new(provider_client);
-            let msg = pry!(transaction.get_args());
-            let address = address_from_bytes(pry!(transaction.get_address()));
-            let code = pry!(transaction.get_code());
-            let params = Params {
-                memory_limit_page: 1000,
-                metering_limit: 11100,
-            };
-
-            let mut contract =
-                tanour::contract::Contract::new(Box::new(adaptor), &address, code, params).unwrap(); // TODO: no unwrap
-
-            let res = match pry!(transaction.get_action().which()) {
-                tanour_capnp::transaction::action::Instantiate(_) => {
-                    contract.call_instantiate(msg).unwrap() // TODO: no unwrap
-                }
-                tanour_capnp::transaction::action::Process(_) => {
-                    contract.call_process(msg).unwrap() // TODO: no unwrap
-                }
-                tanour_capnp::transaction::action::Query(_) => {
-                    contract.call_query(msg).unwrap() // TODO: no unwrap
+        let provider_client = pry!(pry!(params.get()).get_provider());
+        let transaction = pry!(read_transaction(pry!(pry!(params.get()).get_transaction())));
+        let context = self.context.clone();
+        let action = transaction.action;
+        let span = tracing::info_span!(
+            "execute",
+            tx_id = %hex::encode(&transaction.id),
+            action = action.name(),
+            address = tracing::field::Empty,
+        );
+        let worker_span = span.clone();
+
+        Promise::from_future(
+            async move {
+                let permit = context
+                    .executions
+                    .clone()
+                    .acquire_owned()
+                    .await
+                    .map_err(|err| Error::failed(format!("{err}")))?;
+                if context.shutting_down.load(Ordering::Relaxed) {
+                    return Err(Error::failed("server is shutting down".to_string()));
                 }
-            };
-
-            tx.send(res).unwrap(); // TODO: no unwrap
-            Promise::<(), Error>::ok(())
-        });
-
-        Promise::from_future(async move {
-            loop {
-                let msg = rx.try_recv();
-                match msg {
-                    Err(TryRecvError::Empty) => {}
-                    Err(e) => {
-                        return Err(Error::failed(format!("{e}")));
-                    }
-                    Ok(result_data) => {
-                        tokio::time::sleep(std::time::Duration::from_millis(10_u64)).await;
-
-                        let mut builder = results.get().get_result_data().unwrap();
-                        builder.set_data(&result_data);
-
-                        break;
-                    }
-                };
-
-                tokio::task::yield_now().await
+
+                let started = Instant::now();
+                let (provider_tx, provider_rx) = mpsc::unbounded_channel();
+                let adaptor = BlockchainAdaptor::new(provider_tx);
+                let worker_context = context.clone();
+                let execution = context.workers.spawn(move || {
+                    // The permit is released when the worker finishes the execution,
+                    // even if the client cancels the call in the meantime.
+                    let _permit = permit;
+                    worker_span.in_scope(|| {
+                        execute_transaction(
+                            transaction,
+                            adaptor,
+                            &worker_context.config,
+                            worker_context.module_cache.clone(),
+                        )
+                    })
+                });
+
+                // The provider calls from the worker are served here, on the RPC thread,
+                // until the adaptor is dropped at the end of the execution.
+                let serving = serve_provider(provider_client, provider_rx, &context.metrics);
+                let (execution_result, ()) = futures::join!(execution, serving);
+                let execution_result = execution_result
+                    .map_err(|_| Error::failed("execution is aborted on the worker".to_string()))?;
+                observe_execution(
+                    &context.metrics,
+                    action,
+                    &execution_result,
+                    started.elapsed(),
+                );
+
+                set_result_data(results.get().init_result_data(), execution_result);
+                Ok(())
             }
+            .instrument(span),
+        )
+    }
 
-            Ok(())
-        })
+    fn describe(
+        &mut self,
+        params: executor::DescribeParams,
+        mut results: executor::DescribeResults,
+    ) -> Promise<(), Error> {
+        let code = pry!(pry!(params.get()).get_code());
+        let abi = match Abi::from_code(code) {
+            Ok(Some(abi)) => pry!(abi.to_json().map_err(|err| Error::failed(err.to_string()))),
+            Ok(None) => String::new(),
+            Err(err) => return Promise::err(Error::failed(err.to_string())),
+        };
+        results.get().set_abi(abi.as_str());
+        Promise::ok(())
     }
 }
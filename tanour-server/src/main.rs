@@ -2,55 +2,290 @@ pub mod tanour_capnp {
     include!(concat!(env!("OUT_DIR"), "/tanour_capnp.rs"));
 }
 mod adaptor;
+mod config;
 mod executor_impl;
+mod metrics;
+mod worker_pool;
 
 use capnp_rpc::{rpc_twoparty_capnp, twoparty, RpcSystem};
-use executor_impl::ExecutorImpl;
-use futures::{AsyncReadExt, TryFutureExt};
+use clap::Parser;
+use config::{Args, Config};
+use executor_impl::{ExecutorContext, ExecutorImpl};
+use futures::AsyncReadExt;
+use metrics::Metrics;
 use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tanour::cache::ModuleCache;
 use tanour_capnp::executor;
+use worker_pool::WorkerPool;
 
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing_subscriber::EnvFilter;
 
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    simple_logger::SimpleLogger::new().init().unwrap();
+    let config = Config::load(Args::parse())?;
 
-    let args: Vec<String> = ::std::env::args().collect();
-    if args.len() != 2 {
-        println!("usage: {} HOST:PORT", args[0]);
-        return Ok(());
+    let filter = EnvFilter::try_new(&config.log_level)
+        .map_err(|err| format!("invalid log level {}: {err}", config.log_level))?;
+    tracing_subscriber::fmt().with_env_filter(filter).init();
+
+    let metrics = Arc::new(Metrics::new()?);
+    if !config.metrics_listen.is_empty() {
+        let listener = TcpListener::bind(&config.metrics_listen).await?;
+        tracing::info!("serving metrics on {}", config.metrics_listen);
+        tokio::spawn(metrics::serve_metrics(listener, metrics.clone()));
     }
 
-    let addr = args[1]
-        .to_socket_addrs()?
-        .next()
-        .expect("could not parse address");
+    let max_executions = config.limits.max_executions;
+    let context = Arc::new(ExecutorContext {
+        config: config.executor.clone(),
+        module_cache: Arc::new(ModuleCache::new(
+            config.cache.size,
+            config.cache.dir.as_deref(),
+        )?),
+        executions: Arc::new(Semaphore::new(max_executions)),
+        // Each execution occupies a worker until it is finished
+        workers: WorkerPool::new(max_executions)?,
+        metrics,
+        shutting_down: AtomicBool::new(false),
+    });
+    let connections = Arc::new(Semaphore::new(config.limits.max_connections));
+    let shutdown_timeout = Duration::from_secs(config.limits.shutdown_timeout);
 
     tokio::task::LocalSet::new()
         .run_until(async move {
-            let listener = TcpListener::bind(&addr).await?;
+            let tcp_listener = match config.listen.as_str() {
+                "" => None,
+                listen => {
+                    let addr = listen
+                        .to_socket_addrs()?
+                        .next()
+                        .expect("could not parse address");
+                    let listener = TcpListener::bind(&addr).await?;
+                    tracing::info!("listening on {addr}");
+                    Some(listener)
+                }
+            };
+            let unix_listener = match &config.socket {
+                Some(path) => Some(bind_unix_socket(path, config.socket_mode)?),
+                None => None,
+            };
+            if tcp_listener.is_none() && unix_listener.is_none() {
+                return Err("no address or socket to listen on".into());
+            }
+
+            let shutdown = shutdown_signal();
+            tokio::pin!(shutdown);
 
             loop {
-                let (stream, _) = listener.accept().await?;
-                stream.set_nodelay(true)?;
-                let (reader, writer) =
-                    tokio_util::compat::TokioAsyncReadCompatExt::compat(stream).split();
-                let network = twoparty::VatNetwork::new(
-                    reader,
-                    writer,
-                    rpc_twoparty_capnp::Side::Server,
-                    Default::default(),
-                );
-
-                let executor_impl = ExecutorImpl {};
-                let executor_client: executor::Client = capnp_rpc::new_client(executor_impl);
-                let rpc_system = RpcSystem::new(Box::new(network), Some(executor_client.client));
-
-                tokio::task::spawn_local(
-                    rpc_system.map_err(|err| log::error!("rpc_system error : {err}")),
-                );
+                // Waiting for a free connection slot before accepting new connections
+                let permit = tokio::select! {
+                    _ = &mut shutdown => break,
+                    permit = connections.clone().acquire_owned() => permit?,
+                };
+                tokio::select! {
+                    _ = &mut shutdown => break,
+                    accepted = accept_tcp(&tcp_listener) => {
+                        match accepted.and_then(|stream| stream.set_nodelay(true).map(|_| stream)) {
+                            Ok(stream) => spawn_connection(stream, context.clone(), permit),
+                            Err(err) => accept_failed(err).await,
+                        }
+                    }
+                    accepted = accept_unix(&unix_listener) => {
+                        match accepted {
+                            Ok(stream) => spawn_connection(stream, context.clone(), permit),
+                            Err(err) => accept_failed(err).await,
+                        }
+                    }
+                };
+            }
+
+            // The connections are kept open, so the running executions can reach their providers.
+            tracing::info!("shutting down, waiting for the running executions");
+            drop(tcp_listener);
+            drop(unix_listener);
+            if let Some(path) = &config.socket {
+                let _ = remove_unix_socket(path);
+            }
+            context.shutting_down.store(true, Ordering::Relaxed);
+            // The provider calls of the running executions are served while the workers are joined.
+            let draining = context.workers.shutdown();
+            match tokio::time::timeout(shutdown_timeout, draining).await {
+                Ok(_) => tracing::info!("all the executions are finished"),
+                Err(_) => tracing::warn!("shutdown timeout, the running executions are aborted"),
             }
+
+            Ok(())
         })
         .await
 }
+
+/// Serves the `Executor` interface on the connection.
+/// The connection slot is released when the connection is closed.
+fn spawn_connection<S>(stream: S, context: Arc<ExecutorContext>, permit: OwnedSemaphorePermit)
+where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + 'static,
+{
+    let (reader, writer) = tokio_util::compat::TokioAsyncReadCompatExt::compat(stream).split();
+    let network = twoparty::VatNetwork::new(
+        reader,
+        writer,
+        rpc_twoparty_capnp::Side::Server,
+        Default::default(),
+    );
+
+    let executor_impl = ExecutorImpl::new(context);
+    let executor_client: executor::Client = capnp_rpc::new_client(executor_impl);
+    let rpc_system = RpcSystem::new(Box::new(network), Some(executor_client.client));
+
+    tokio::task::spawn_local(async move {
+        if let Err(err) = rpc_system.await {
+            tracing::error!("rpc_system error : {err}");
+        }
+        drop(permit);
+    });
+}
+
+/// Accepts a TCP connection, it never completes if there is no TCP listener.
+/// The time to wait before accepting again, when accepting a connection fails.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Logs the failed accept, the server keeps accepting the other connections.
+/// Accepting is paused for a while unless only the accepted connection failed,
+/// so the server doesn't spin when it runs out of file descriptors.
+pub(crate) async fn accept_failed(err: std::io::Error) {
+    tracing::error!("unable to accept the connection: {err}");
+    match err.kind() {
+        std::io::ErrorKind::ConnectionAborted
+        | std::io::ErrorKind::ConnectionReset
+        | std::io::ErrorKind::Interrupted => {}
+        _ => tokio::time::sleep(ACCEPT_BACKOFF).await,
+    }
+}
+
+async fn accept_tcp(listener: &Option<TcpListener>) -> std::io::Result<TcpStream> {
+    match listener {
+        Some(listener) => listener.accept().await.map(|(stream, _)| stream),
+        None => futures::future::pending().await,
+    }
+}
+
+#[cfg(unix)]
+type UnixListener = tokio::net::UnixListener;
+
+// Unix domain sockets are not supported on the other platforms.
+#[cfg(not(unix))]
+type UnixListener = std::convert::Infallible;
+
+/// Binds the Unix domain socket and sets its file permissions.
+/// A stale socket file from the previous run is removed, but other files are not.
+///
+/// The socket is bound in a private directory and it is renamed into place
+/// after its permissions are set, so it is never accessible with the default permissions.
+#[cfg(unix)]
+fn bind_unix_socket(path: &Path, mode: u32) -> std::io::Result<UnixListener> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    remove_unix_socket(path)?;
+
+    let file_name = path.file_name().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "invalid socket path")
+    })?;
+    let private_dir = path.with_file_name(format!(
+        ".{}.{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private_dir)?;
+
+    let private_path = private_dir.join("socket");
+    let bound = UnixListener::bind(&private_path).and_then(|listener| {
+        std::fs::set_permissions(&private_path, std::fs::Permissions::from_mode(mode))?;
+        std::fs::rename(&private_path, path)?;
+        Ok(listener)
+    });
+    let _ = std::fs::remove_file(&private_path);
+    std::fs::remove_dir(&private_dir)?;
+
+    let listener = bound?;
+    tracing::info!("listening on {path:?}");
+    Ok(listener)
+}
+
+/// Removes the Unix domain socket file, if it exists.
+/// It fails if the path is not a socket, so other files are not removed by mistake.
+#[cfg(unix)]
+fn remove_unix_socket(path: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        Ok(_) => Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("{path:?} exists and it is not a socket"),
+        )),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(not(unix))]
+fn remove_unix_socket(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn bind_unix_socket(_path: &Path, _mode: u32) -> std::io::Result<UnixListener> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "unix domain sockets are not supported on this platform",
+    ))
+}
+
+/// Accepts a Unix domain socket connection,
+/// it never completes if there is no Unix domain socket listener.
+#[cfg(unix)]
+async fn accept_unix(listener: &Option<UnixListener>) -> std::io::Result<tokio::net::UnixStream> {
+    match listener {
+        Some(listener) => listener.accept().await.map(|(stream, _)| stream),
+        None => futures::future::pending().await,
+    }
+}
+
+#[cfg(not(unix))]
+async fn accept_unix(_listener: &Option<UnixListener>) -> std::io::Result<TcpStream> {
+    futures::future::pending().await
+}
+
+/// Waits for SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {},
+                    _ = sigterm.recv() => {},
+                }
+            }
+            Err(err) => {
+                tracing::error!("unable to listen for SIGTERM: {err}");
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
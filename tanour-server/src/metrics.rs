@@ -0,0 +1,239 @@
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, Opts,
+    Registry, TextEncoder,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tanour::contract::CompileInfo;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// The time to wait for the scrape request, so the idle clients don't hold the tasks.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The metrics of the server, in Prometheus format.
+pub struct Metrics {
+    registry: Registry,
+    // Executions by action and status: success, error or out_of_gas
+    executions: IntCounterVec,
+    // Failed executions by action and error code
+    errors: IntCounterVec,
+    gas_used: HistogramVec,
+    execution_time: HistogramVec,
+    compile_time: Histogram,
+    // Module cache lookups by result: hit or miss
+    cache: IntCounterVec,
+    provider_latency: HistogramVec,
+    provider_errors: IntCounterVec,
+}
+
+fn register<T: prometheus::core::Collector + Clone + 'static>(
+    registry: &Registry,
+    collector: T,
+) -> prometheus::Result<T> {
+    registry.register(Box::new(collector.clone()))?;
+    Ok(collector)
+}
+
+impl Metrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new_custom(Some("tanour".to_string()), None)?;
+        // From 1 millisecond to about 16 seconds
+        let time_buckets = exponential_buckets(0.001, 2.0, 15)?;
+
+        Ok(Metrics {
+            executions: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("executions_total", "The number of the executions"),
+                    &["action", "status"],
+                )?,
+            )?,
+            errors: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "execution_errors_total",
+                        "The number of the failed executions",
+                    ),
+                    &["action", "code"],
+                )?,
+            )?,
+            gas_used: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("gas_used", "The consumed metering points")
+                        .buckets(exponential_buckets(100.0, 4.0, 12)?),
+                    &["action"],
+                )?,
+            )?,
+            execution_time: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("execution_seconds", "The duration of the executions")
+                        .buckets(time_buckets.clone()),
+                    &["action"],
+                )?,
+            )?,
+            compile_time: register(
+                &registry,
+                Histogram::with_opts(
+                    HistogramOpts::new(
+                        "compile_seconds",
+                        "The duration of compiling or loading the modules from the cache",
+                    )
+                    .buckets(time_buckets.clone()),
+                )?,
+            )?,
+            cache: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new("module_cache_total", "The module cache lookups"),
+                    &["result"],
+                )?,
+            )?,
+            provider_latency: register(
+                &registry,
+                HistogramVec::new(
+                    HistogramOpts::new("provider_rpc_seconds", "The latency of the provider calls")
+                        .buckets(time_buckets),
+                    &["method"],
+                )?,
+            )?,
+            provider_errors: register(
+                &registry,
+                IntCounterVec::new(
+                    Opts::new(
+                        "provider_rpc_errors_total",
+                        "The number of the failed provider calls",
+                    ),
+                    &["method"],
+                )?,
+            )?,
+            registry,
+        })
+    }
+
+    /// Records a finished execution.
+    /// The error code is given if the execution is failed.
+    pub fn observe_execution(
+        &self,
+        action: &str,
+        status: &str,
+        error_code: Option<u32>,
+        gas_used: u64,
+        duration: Duration,
+    ) {
+        self.executions.with_label_values(&[action, status]).inc();
+        if let Some(code) = error_code {
+            self.errors
+                .with_label_values(&[action, &code.to_string()])
+                .inc();
+        }
+        self.gas_used
+            .with_label_values(&[action])
+            .observe(gas_used as f64);
+        self.execution_time
+            .with_label_values(&[action])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn observe_compile(&self, info: &CompileInfo) {
+        self.compile_time.observe(info.duration.as_secs_f64());
+        let result = if info.cache_hit { "hit" } else { "miss" };
+        self.cache.with_label_values(&[result]).inc();
+    }
+
+    pub fn observe_provider_call(&self, method: &str, duration: Duration, failed: bool) {
+        self.provider_latency
+            .with_label_values(&[method])
+            .observe(duration.as_secs_f64());
+        if failed {
+            self.provider_errors.with_label_values(&[method]).inc();
+        }
+    }
+
+    /// Returns the metrics in the Prometheus text format.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        if let Err(err) = TextEncoder::new().encode(&self.registry.gather(), &mut buf) {
+            tracing::error!("unable to encode the metrics: {err}");
+        }
+        buf
+    }
+}
+
+/// Serves the metrics over HTTP, for any request path.
+pub async fn serve_metrics(listener: TcpListener, metrics: Arc<Metrics>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = write_metrics(stream, &metrics).await {
+                        tracing::debug!("unable to write the metrics: {err}");
+                    }
+                });
+            }
+            Err(err) => crate::accept_failed(err).await,
+        }
+    }
+}
+
+async fn write_metrics(mut stream: TcpStream, metrics: &Metrics) -> std::io::Result<()> {
+    // The request is not parsed, reading it is only to not reset the connection.
+    let mut request = [0; 1024];
+    tokio::time::timeout(READ_TIMEOUT, stream.read(&mut request))
+        .await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "reading the request"))??;
+
+    let body = metrics.encode();
+    let header = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_serve_metrics() {
+        let metrics = Arc::new(Metrics::new().unwrap());
+        metrics.observe_execution("process", "error", Some(9), 1000, Duration::from_millis(5));
+        metrics.observe_compile(&CompileInfo {
+            duration: Duration::from_millis(20),
+            cache_hit: false,
+        });
+        metrics.observe_provider_call("read_page", Duration::from_millis(1), true);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_metrics(listener, metrics));
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        for series in [
+            r#"tanour_executions_total{action="process",status="error"} 1"#,
+            r#"tanour_execution_errors_total{action="process",code="9"} 1"#,
+            r#"tanour_gas_used_count{action="process"} 1"#,
+            "tanour_compile_seconds_count 1",
+            r#"tanour_module_cache_total{result="miss"} 1"#,
+            r#"tanour_provider_rpc_seconds_count{method="read_page"} 1"#,
+            r#"tanour_provider_rpc_errors_total{method="read_page"} 1"#,
+        ] {
+            assert!(response.contains(series), "{series} is missing");
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use tokio::sync::{mpsc as tokio_mpsc, oneshot};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A pool of worker threads to execute the contracts.
+///
+/// The contract executions are blocking, so they run on the workers
+/// and don't stall the RPC thread.
+/// The workers stop when the pool is shut down or dropped, and the queue is empty.
+pub struct WorkerPool {
+    // It is taken on shutdown, so the workers stop after the queued jobs
+    sender: Mutex<Option<mpsc::Sender<Job>>>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+    // Each worker holds a sender, the receiver is closed when all the workers are stopped
+    stopped: tokio::sync::Mutex<tokio_mpsc::Receiver<()>>,
+}
+
+impl WorkerPool {
+    /// Creates a new pool with the given number of workers.
+    pub fn new(size: usize) -> std::io::Result<Self> {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let (stopped_tx, stopped_rx) = tokio_mpsc::channel::<()>(1);
+
+        let mut handles = Vec::with_capacity(size);
+        for id in 0..size {
+            let receiver = receiver.clone();
+            let stopped_tx = stopped_tx.clone();
+            let handle = std::thread::Builder::new()
+                .name(format!("tanour-worker-{id}"))
+                .spawn(move || {
+                    let _stopped_tx = stopped_tx;
+                    loop {
+                        // The lock is released before running the job
+                        let job = match receiver.lock() {
+                            Ok(receiver) => receiver.recv(),
+                            Err(_) => break,
+                        };
+                        match job {
+                            Ok(job) => job(),
+                            // The pool is shut down or dropped
+                            Err(_) => break,
+                        }
+                    }
+                })?;
+            handles.push(handle);
+        }
+
+        Ok(WorkerPool {
+            sender: Mutex::new(Some(sender)),
+            handles: Mutex::new(handles),
+            stopped: tokio::sync::Mutex::new(stopped_rx),
+        })
+    }
+
+    /// Stops accepting new jobs and waits for the workers to finish the queued jobs,
+    /// then joins the worker threads.
+    pub async fn shutdown(&self) {
+        if let Ok(mut sender) = self.sender.lock() {
+            sender.take();
+        }
+
+        // Receiving fails when all the workers are stopped and their senders are dropped
+        while self.stopped.lock().await.recv().await.is_some() {}
+
+        let handles = match self.handles.lock() {
+            Ok(mut handles) => std::mem::take(&mut *handles),
+            Err(_) => Vec::new(),
+        };
+        for handle in handles {
+            let _ = handle.join();
+        }
+    }
+
+    /// Runs the function on a worker and returns a receiver for its result.
+    /// The receiver fails if the function panics, the panic is caught and the worker keeps running.
+    /// If the receiver is dropped before a worker picks the function, it is not run.
+    pub fn spawn<F, T>(&self, f: F) -> oneshot::Receiver<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let job = Box::new(move || {
+            // The receiver is dropped if the result is not needed anymore
+            if tx.is_closed() {
+                return;
+            }
+            match catch_unwind(AssertUnwindSafe(f)) {
+                Ok(result) => {
+                    let _ = tx.send(result);
+                }
+                // The sender is dropped, so the receiver fails
+                Err(_) => tracing::error!("the job panicked on the worker"),
+            }
+        });
+
+        // Sending fails if the pool is shut down or all the workers are stopped,
+        // then the job is dropped and the receiver fails.
+        if let Ok(sender) = self.sender.lock() {
+            if let Some(sender) = sender.as_ref() {
+                let _ = sender.send(job);
+            }
+        }
+        rx
+    }
+}
@@ -0,0 +1,412 @@
+//! The contract ABI, describing the messages that a contract accepts.
+//!
+//! The ABI is kept in the `pactus_abi` custom section of the Wasm module as a JSON text.
+//! The messages are encoded in CBOR, in the same layout as the `minicbor` derive macros:
+//! structs are arrays of their fields and enums are `[index, fields]` arrays.
+
+use crate::codec;
+use crate::error::{Error, Result};
+use minicbor::Encoder;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use wasmer::wasmparser::{Parser, Payload};
+
+/// The name of the custom section that keeps the ABI.
+pub const ABI_SECTION: &str = "pactus_abi";
+
+/// The type of a message or a field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TypeDef {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    I8,
+    I16,
+    I32,
+    I64,
+    String,
+    Bytes,
+    Option(Box<TypeDef>),
+    Array(Box<TypeDef>),
+    Struct(Vec<Field>),
+    Enum(Vec<Variant>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: TypeDef,
+    /// The index of the field, the position of the field if it is not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Variant {
+    pub name: String,
+    #[serde(default)]
+    pub fields: Vec<Field>,
+    /// The index of the variant, the position of the variant if it is not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+}
+
+/// The message schemas of the contract's entry points.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Abi {
+    pub instantiate: Option<TypeDef>,
+    pub process: Option<TypeDef>,
+    pub query: Option<TypeDef>,
+}
+
+fn abi_error(msg: String) -> Error {
+    Error::AbiError { msg }
+}
+
+/// Finds the custom section with the given name in the Wasm code.
+pub fn custom_section<'a>(code: &'a [u8], name: &str) -> Result<Option<&'a [u8]>> {
+    for payload in Parser::new(0).parse_all(code) {
+        let payload =
+            payload.map_err(|original| abi_error(format!("invalid wasm code: {original}")))?;
+        if let Payload::CustomSection {
+            name: section_name,
+            data,
+            ..
+        } = payload
+        {
+            if section_name == name {
+                return Ok(Some(data));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+impl Abi {
+    /// Parses the ABI from the JSON text.
+    pub fn from_json(json: &str) -> Result<Self> {
+        serde_json::from_str(json).map_err(|original| abi_error(format!("{original}")))
+    }
+
+    /// Reads the ABI from the `pactus_abi` custom section of the code, if exists.
+    pub fn from_code(code: &[u8]) -> Result<Option<Self>> {
+        match custom_section(code, ABI_SECTION)? {
+            Some(data) => {
+                let json = std::str::from_utf8(data)
+                    .map_err(|original| abi_error(format!("{original}")))?;
+                Ok(Some(Abi::from_json(json)?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the ABI as a JSON text.
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|original| abi_error(format!("{original}")))
+    }
+
+    /// Returns the message type of the entry point: `instantiate`, `process` or `query`.
+    pub fn message(&self, entry: &str) -> Result<&TypeDef> {
+        let ty = match entry {
+            "instantiate" => &self.instantiate,
+            "process" => &self.process,
+            "query" => &self.query,
+            _ => return Err(abi_error(format!("unknown entry point: {entry}"))),
+        };
+        ty.as_ref()
+            .ok_or_else(|| abi_error(format!("no message defined for {entry}")))
+    }
+
+    /// Validates the JSON message against the schema of the entry point and encodes it into CBOR.
+    ///
+    /// Structs are JSON objects, enum variants are JSON objects with the variant name as the key,
+    /// or only the variant name if it has no fields. Bytes are hex strings.
+    pub fn encode(&self, entry: &str, msg: &Value) -> Result<Vec<u8>> {
+        let ty = self.message(entry)?;
+        let mut buf = Vec::new();
+        let mut encoder = Encoder::new(&mut buf);
+        encode_value(&mut encoder, ty, msg, entry)?;
+        Ok(buf)
+    }
+}
+
+fn encode_error<E: std::fmt::Display>(original: E) -> Error {
+    abi_error(format!("{original}"))
+}
+
+fn mismatch(path: &str, expected: &str, value: &Value) -> Error {
+    abi_error(format!("{path}: expected {expected}, got {value}"))
+}
+
+fn encode_int(
+    e: &mut Encoder<&mut Vec<u8>>,
+    path: &str,
+    ty: &TypeDef,
+    value: &Value,
+) -> Result<()> {
+    let (min, max, name) = match ty {
+        TypeDef::U8 => (0, u8::MAX as i128, "u8"),
+        TypeDef::U16 => (0, u16::MAX as i128, "u16"),
+        TypeDef::U32 => (0, u32::MAX as i128, "u32"),
+        TypeDef::U64 => (0, u64::MAX as i128, "u64"),
+        TypeDef::I8 => (i8::MIN as i128, i8::MAX as i128, "i8"),
+        TypeDef::I16 => (i16::MIN as i128, i16::MAX as i128, "i16"),
+        TypeDef::I32 => (i32::MIN as i128, i32::MAX as i128, "i32"),
+        _ => (i64::MIN as i128, i64::MAX as i128, "i64"),
+    };
+
+    let n = match (value.as_u64(), value.as_i64()) {
+        (Some(u), _) => u as i128,
+        (None, Some(i)) => i as i128,
+        _ => return Err(mismatch(path, name, value)),
+    };
+    if n < min || n > max {
+        return Err(mismatch(path, name, value));
+    }
+
+    if n >= 0 {
+        e.u64(n as u64).map_err(encode_error)?;
+    } else {
+        e.i64(n as i64).map_err(encode_error)?;
+    }
+    Ok(())
+}
+
+/// Encodes the fields as an array, ordered by their indices.
+/// Missing indices are encoded as null.
+fn encode_fields(
+    e: &mut Encoder<&mut Vec<u8>>,
+    fields: &[Field],
+    value: &Value,
+    path: &str,
+) -> Result<()> {
+    let empty = serde_json::Map::new();
+    let obj = match value {
+        Value::Object(obj) => obj,
+        Value::Null if fields.is_empty() => &empty,
+        _ => return Err(mismatch(path, "object", value)),
+    };
+
+    for key in obj.keys() {
+        if !fields.iter().any(|f| &f.name == key) {
+            return Err(abi_error(format!("{path}: unknown field {key}")));
+        }
+    }
+
+    let indices: Vec<u32> = fields
+        .iter()
+        .enumerate()
+        .map(|(i, f)| f.index.unwrap_or(i as u32))
+        .collect();
+    let len = indices.iter().map(|i| i + 1).max().unwrap_or(0);
+
+    e.array(len as u64).map_err(encode_error)?;
+    for index in 0..len {
+        match indices.iter().position(|i| *i == index) {
+            Some(pos) => {
+                let field = &fields[pos];
+                let field_path = format!("{path}.{}", field.name);
+                let field_value = obj.get(&field.name).unwrap_or(&Value::Null);
+                encode_value(e, &field.ty, field_value, &field_path)?;
+            }
+            None => {
+                e.null().map_err(encode_error)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn encode_value(
+    e: &mut Encoder<&mut Vec<u8>>,
+    ty: &TypeDef,
+    value: &Value,
+    path: &str,
+) -> Result<()> {
+    match ty {
+        TypeDef::Bool => {
+            let b = value
+                .as_bool()
+                .ok_or_else(|| mismatch(path, "bool", value))?;
+            e.bool(b).map_err(encode_error)?;
+        }
+        TypeDef::U8
+        | TypeDef::U16
+        | TypeDef::U32
+        | TypeDef::U64
+        | TypeDef::I8
+        | TypeDef::I16
+        | TypeDef::I32
+        | TypeDef::I64 => encode_int(e, path, ty, value)?,
+        TypeDef::String => {
+            let s = value
+                .as_str()
+                .ok_or_else(|| mismatch(path, "string", value))?;
+            e.str(s).map_err(encode_error)?;
+        }
+        TypeDef::Bytes => {
+            let s = value
+                .as_str()
+                .ok_or_else(|| mismatch(path, "hex string", value))?;
+            let hex_str = s
+                .strip_prefix("h'")
+                .and_then(|s| s.strip_suffix('\''))
+                .unwrap_or(s);
+            let bytes = hex::decode(hex_str).map_err(|_| mismatch(path, "hex string", value))?;
+            e.bytes(&bytes).map_err(encode_error)?;
+        }
+        TypeDef::Option(inner) => match value {
+            Value::Null => {
+                e.null().map_err(encode_error)?;
+            }
+            _ => encode_value(e, inner, value, path)?,
+        },
+        TypeDef::Array(inner) => {
+            let items = value
+                .as_array()
+                .ok_or_else(|| mismatch(path, "array", value))?;
+            e.array(items.len() as u64).map_err(encode_error)?;
+            for (i, item) in items.iter().enumerate() {
+                encode_value(e, inner, item, &format!("{path}[{i}]"))?;
+            }
+        }
+        TypeDef::Struct(fields) => encode_fields(e, fields, value, path)?,
+        TypeDef::Enum(variants) => {
+            let (name, fields_value) = match value {
+                Value::String(name) => (name, &Value::Null),
+                Value::Object(obj) if obj.len() == 1 => obj.iter().next().unwrap(),
+                _ => return Err(mismatch(path, "enum variant", value)),
+            };
+            let (pos, variant) = variants
+                .iter()
+                .enumerate()
+                .find(|(_, v)| &v.name == name)
+                .ok_or_else(|| abi_error(format!("{path}: unknown variant {name}")))?;
+
+            e.array(2).map_err(encode_error)?;
+            e.u32(variant.index.unwrap_or(pos as u32))
+                .map_err(encode_error)?;
+            encode_fields(e, &variant.fields, fields_value, &format!("{path}.{name}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Encodes the JSON message into CBOR, using the ABI if it is available.
+/// Without the ABI, the JSON is encoded as it is.
+pub fn encode_message(abi: Option<&Abi>, entry: &str, json: &str) -> Result<Vec<u8>> {
+    match abi {
+        Some(abi) => {
+            let msg: Value =
+                serde_json::from_str(json).map_err(|original| abi_error(format!("{original}")))?;
+            abi.encode(entry, &msg)
+        }
+        None => Ok(codec::json_to_cbor(json)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_contract::message::{InstantiateMsg, ProcMsg, QueryMsg};
+
+    const TEST_ABI: &str = r#"{
+    "instantiate": {"struct": []},
+    "process": {"enum": [
+        {"name": "Null"},
+        {"name": "SetMessage", "fields": [{"name": "msg", "type": "string"}]}
+    ]},
+    "query": {"enum": [
+        {"name": "GetMessage"},
+        {"name": "Hasher", "fields": [{"name": "data", "type": {"array": "u8"}}]},
+        {"name": "Divider", "fields": [{"name": "a", "type": "i32"}, {"name": "b", "type": "i32"}]}
+    ]}
+}"#;
+
+    /// Appends a custom section to the Wasm code.
+    fn append_custom_section(code: &mut Vec<u8>, name: &str, data: &[u8]) {
+        let mut section = Vec::new();
+        section.push(name.len() as u8);
+        section.extend_from_slice(name.as_bytes());
+        section.extend_from_slice(data);
+
+        code.push(0);
+        let mut size = section.len() as u32;
+        loop {
+            let byte = (size & 0x7f) as u8;
+            size >>= 7;
+            if size == 0 {
+                code.push(byte);
+                break;
+            }
+            code.push(byte | 0x80);
+        }
+        code.extend_from_slice(&section);
+    }
+
+    #[test]
+    fn test_from_code() {
+        let mut code = wat::parse_str("(module)").unwrap();
+        assert_eq!(Abi::from_code(&code).unwrap(), None);
+
+        append_custom_section(&mut code, "other", b"data");
+        append_custom_section(&mut code, ABI_SECTION, TEST_ABI.as_bytes());
+        let abi = Abi::from_code(&code).unwrap().unwrap();
+        assert_eq!(abi, Abi::from_json(TEST_ABI).unwrap());
+        assert_eq!(Abi::from_json(&abi.to_json().unwrap()).unwrap(), abi);
+    }
+
+    #[test]
+    fn test_invalid_code() {
+        assert!(Abi::from_code(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        let abi = Abi::from_json(TEST_ABI).unwrap();
+
+        let encoded = abi.encode("instantiate", &serde_json::json!({})).unwrap();
+        assert_eq!(encoded, minicbor::to_vec(InstantiateMsg {}).unwrap());
+
+        let encoded = abi.encode("process", &serde_json::json!("Null")).unwrap();
+        assert_eq!(encoded, minicbor::to_vec(ProcMsg::Null).unwrap());
+
+        let msg = serde_json::json!({"SetMessage": {"msg": "hello world!"}});
+        let encoded = abi.encode("process", &msg).unwrap();
+        let expected = ProcMsg::SetMessage {
+            msg: "hello world!".to_string(),
+        };
+        assert_eq!(encoded, minicbor::to_vec(expected).unwrap());
+
+        let msg = serde_json::json!({"Divider": {"a": -10, "b": 2}});
+        let encoded = abi.encode("query", &msg).unwrap();
+        let expected = QueryMsg::Divider { a: -10, b: 2 };
+        assert_eq!(encoded, minicbor::to_vec(expected).unwrap());
+    }
+
+    #[test]
+    fn test_validate() {
+        let abi = Abi::from_json(TEST_ABI).unwrap();
+
+        let invalid_msgs = [
+            serde_json::json!("Unknown"),
+            serde_json::json!({"SetMessage": {"msg": 1}}),
+            serde_json::json!({"SetMessage": {"msg": "hi", "extra": 1}}),
+        ];
+        for msg in invalid_msgs {
+            assert!(matches!(
+                abi.encode("process", &msg),
+                Err(Error::AbiError { .. })
+            ));
+        }
+
+        let msg = serde_json::json!({"Divider": {"a": 1, "b": 3_000_000_000_u64}});
+        assert!(abi.encode("query", &msg).is_err());
+        assert!(abi.encode("unknown", &msg).is_err());
+    }
+}
@@ -5,8 +5,14 @@ use mockall::{automock, predicate::*};
 #[automock]
 pub trait BlockchainAPI: Send + 'static {
     fn page_size(&self) -> Result<u32>;
+    /// Returns the current size of the contract's storage in bytes.
+    fn storage_size(&self) -> Result<u32>;
     fn read_page(&self, page_no: u32) -> Result<Vec<u8>>;
+    /// Reads multiple pages in one call. The pages are returned in the same order.
+    fn read_pages(&self, page_nos: &[u32]) -> Result<Vec<Vec<u8>>>;
     fn write_page(&self, page_no: u32, data: &[u8]) -> Result<()>;
+    /// Writes multiple pages in one call. The pages should be applied atomically.
+    fn write_pages(&self, pages: &[(u32, Vec<u8>)]) -> Result<()>;
     fn exist(&self, address: &Address) -> Result<bool>;
     // TODO: maybe better we return a block_info, including hash, time, number and proposer address
     fn current_block_number(&self) -> u32;
@@ -0,0 +1,170 @@
+//! Caching the compiled modules, so the same code is not compiled again.
+//!
+//! The compiled modules are kept serialized in memory, up to the cache capacity.
+//! If a cache directory is set, they are also kept on disk and survive restarts.
+//! Each file on disk starts with the checksum of the module, and the modules with
+//! a mismatched checksum are ignored. The checksum detects corrupted and truncated files,
+//! but not tampering, hence the cache directory should be writable only by the executor.
+
+use crate::error::Result;
+use blake2::digest::{Update, VariableOutput};
+use blake2::VarBlake2b;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+pub type ModuleKey = [u8; 32];
+
+const CHECKSUM_SIZE: usize = 32;
+
+fn checksum(data: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let mut hasher = VarBlake2b::new(CHECKSUM_SIZE).unwrap();
+    hasher.update(data);
+
+    let mut checksum = [0; CHECKSUM_SIZE];
+    hasher.finalize_variable(|res| checksum.copy_from_slice(res));
+    checksum
+}
+
+#[derive(Debug)]
+pub struct ModuleCache {
+    capacity: usize,
+    dir: Option<PathBuf>,
+    // The most recently used modules are at the back
+    entries: Mutex<VecDeque<(ModuleKey, Vec<u8>)>>,
+}
+
+impl ModuleCache {
+    /// Creates a new module cache, keeping at most `capacity` modules in memory.
+    /// The cache directory is created if it doesn't exist.
+    pub fn new(capacity: usize, dir: Option<&Path>) -> Result<Self> {
+        if let Some(dir) = dir {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        Ok(ModuleCache {
+            capacity,
+            dir: dir.map(Path::to_path_buf),
+            entries: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    fn module_path(&self, key: &ModuleKey) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{}.module", hex::encode(key))))
+    }
+
+    /// Returns the serialized module, looking up the memory first and then the disk.
+    pub(crate) fn get(&self, key: &ModuleKey) -> Option<Vec<u8>> {
+        {
+            let mut entries = self.entries.lock().ok()?;
+            if let Some(pos) = entries.iter().position(|(k, _)| k == key) {
+                let entry = entries.remove(pos)?;
+                let data = entry.1.clone();
+                entries.push_back(entry);
+                return Some(data);
+            }
+        }
+
+        let path = self.module_path(key)?;
+        let file = std::fs::read(&path).ok()?;
+        if file.len() < CHECKSUM_SIZE || file[..CHECKSUM_SIZE] != checksum(&file[CHECKSUM_SIZE..]) {
+            tracing::warn!("the module cache {path:?} is corrupted, ignoring it");
+            return None;
+        }
+        let data = file[CHECKSUM_SIZE..].to_vec();
+        self.insert(key, data.clone());
+        Some(data)
+    }
+
+    /// Keeps the serialized module in memory and on disk.
+    /// Failing to write the module on disk is not an error, it will be compiled again.
+    pub(crate) fn put(&self, key: &ModuleKey, data: Vec<u8>) {
+        if let Some(path) = self.module_path(key) {
+            if let Err(err) = write_module(&path, &data) {
+                tracing::warn!("unable to write the module cache {path:?}: {err}");
+            }
+        }
+        self.insert(key, data);
+    }
+
+    fn insert(&self, key: &ModuleKey, data: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Ok(mut entries) = self.entries.lock() {
+            entries.retain(|(k, _)| k != key);
+            if entries.len() >= self.capacity {
+                entries.pop_front();
+            }
+            entries.push_back((*key, data));
+        }
+    }
+}
+
+/// Writes the checksum and the module into a temporary file and renames it,
+/// so a module file is never partially written.
+fn write_module(path: &Path, data: &[u8]) -> std::io::Result<()> {
+    let tmp_path = path.with_extension("module.tmp");
+    let mut file = Vec::with_capacity(CHECKSUM_SIZE + data.len());
+    file.extend_from_slice(&checksum(data));
+    file.extend_from_slice(data);
+    std::fs::write(&tmp_path, file)?;
+    std::fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capacity() {
+        let cache = ModuleCache::new(2, None).unwrap();
+        cache.put(&[1; 32], vec![1]);
+        cache.put(&[2; 32], vec![2]);
+        assert_eq!(cache.get(&[1; 32]), Some(vec![1]));
+
+        // The least recently used module is evicted
+        cache.put(&[3; 32], vec![3]);
+        assert_eq!(cache.get(&[2; 32]), None);
+        assert_eq!(cache.get(&[1; 32]), Some(vec![1]));
+        assert_eq!(cache.get(&[3; 32]), Some(vec![3]));
+    }
+
+    #[test]
+    fn test_dir() {
+        let name: u64 = rand::random();
+        let dir = std::env::temp_dir().join(format!("tanour-cache-{name:x}"));
+
+        let cache = ModuleCache::new(0, Some(&dir)).unwrap();
+        cache.put(&[1; 32], vec![1, 2, 3]);
+
+        let cache = ModuleCache::new(1, Some(&dir)).unwrap();
+        assert_eq!(cache.get(&[1; 32]), Some(vec![1, 2, 3]));
+        assert_eq!(cache.get(&[2; 32]), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_file() {
+        let name: u64 = rand::random();
+        let dir = std::env::temp_dir().join(format!("tanour-cache-{name:x}"));
+
+        let cache = ModuleCache::new(0, Some(&dir)).unwrap();
+        cache.put(&[1; 32], vec![1, 2, 3]);
+        cache.put(&[2; 32], vec![4, 5, 6]);
+
+        let path = cache.module_path(&[1; 32]).unwrap();
+        let mut file = std::fs::read(&path).unwrap();
+        *file.last_mut().unwrap() = 0xff;
+        std::fs::write(&path, file).unwrap();
+        std::fs::write(cache.module_path(&[2; 32]).unwrap(), [1, 2]).unwrap();
+
+        assert_eq!(cache.get(&[1; 32]), None);
+        assert_eq!(cache.get(&[2; 32]), None);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+use crate::blockchain_api::BlockchainAPI;
+use crate::error::{Error, Result};
+use crate::{address_to_hex, Address};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// A file-backed blockchain that keeps the storage of each contract in a separate file.
+///
+/// The storage files are kept in a directory and named by the contract address.
+/// A contract exists if its storage file exists in the directory.
+/// The storage file is created when the pages are written for the first time,
+/// so a contract can be deployed at the address of a new chain.
+/// The pages are written into a copy of the storage file, which replaces the storage file
+/// afterward, so a crash never leaves the storage partially written.
+#[derive(Debug)]
+pub struct FileChain {
+    dir: PathBuf,
+    path: PathBuf,
+    page_size: u32,
+    block_number: u32,
+}
+
+impl FileChain {
+    /// Opens the storage of the contract in the directory.
+    /// The directory is created if it doesn't exist.
+    pub fn new(dir: &Path, address: &Address, page_size: u32) -> Result<Self> {
+        std::fs::create_dir_all(dir)?;
+
+        Ok(FileChain {
+            dir: dir.to_path_buf(),
+            path: storage_path(dir, address),
+            page_size,
+            block_number: 0,
+        })
+    }
+
+    /// Sets the current block number.
+    pub fn set_block_number(&mut self, block_number: u32) {
+        self.block_number = block_number;
+    }
+
+    /// Returns the size of the storage file, it is zero if the file doesn't exist yet.
+    fn file_size(&self) -> Result<u32> {
+        let size = match std::fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err.into()),
+        };
+        u32::try_from(size).map_err(|_| Error::ProviderError {
+            msg: format!("storage file is too large: {size} bytes"),
+        })
+    }
+
+    fn write_page_at(&self, mut file: &File, page_no: u32, data: &[u8]) -> Result<()> {
+        let offset = page_no as u64 * self.page_size as u64;
+        if data.is_empty() {
+            // Zero pages beyond the end of the file are already zero
+            if offset >= file.metadata()?.len() {
+                return Ok(());
+            }
+            let zeros = vec![0; self.page_size as usize];
+            return self.write_page_at(file, page_no, &zeros);
+        }
+
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        Ok(())
+    }
+}
+
+/// Returns the path of the storage file for the given address.
+pub fn storage_path(dir: &Path, address: &Address) -> PathBuf {
+    dir.join(format!("{}.storage", address_to_hex(address)))
+}
+
+impl BlockchainAPI for FileChain {
+    fn page_size(&self) -> Result<u32> {
+        Ok(self.page_size)
+    }
+
+    fn storage_size(&self) -> Result<u32> {
+        self.file_size()
+    }
+
+    fn read_page(&self, page_no: u32) -> Result<Vec<u8>> {
+        let offset = page_no as u64 * self.page_size as u64;
+        if offset >= self.file_size()? as u64 {
+            // Zero page
+            return Ok(Vec::new());
+        }
+
+        let mut file = File::open(&self.path)?;
+        let mut data = Vec::with_capacity(self.page_size as usize);
+        file.seek(SeekFrom::Start(offset))?;
+        file.take(self.page_size as u64).read_to_end(&mut data)?;
+        data.resize(self.page_size as usize, 0);
+
+        Ok(data)
+    }
+
+    fn read_pages(&self, page_nos: &[u32]) -> Result<Vec<Vec<u8>>> {
+        page_nos
+            .iter()
+            .map(|page_no| self.read_page(*page_no))
+            .collect()
+    }
+
+    fn write_page(&self, page_no: u32, data: &[u8]) -> Result<()> {
+        self.write_pages(&[(page_no, data.to_vec())])
+    }
+
+    fn write_pages(&self, pages: &[(u32, Vec<u8>)]) -> Result<()> {
+        let temp_path = self.path.with_extension("storage.tmp");
+        if self.path.exists() {
+            std::fs::copy(&self.path, &temp_path)?;
+        } else {
+            // The storage file is created on the first write
+            File::create(&temp_path)?;
+        }
+
+        let file = OpenOptions::new().write(true).open(&temp_path)?;
+        for (page_no, data) in pages {
+            self.write_page_at(&file, *page_no, data)?;
+        }
+        file.sync_all()?;
+
+        // Renaming is atomic, the storage file has either all the pages or none of them
+        std::fs::rename(&temp_path, &self.path)?;
+        #[cfg(unix)]
+        File::open(&self.dir)?.sync_all()?;
+        Ok(())
+    }
+
+    fn exist(&self, address: &Address) -> Result<bool> {
+        Ok(storage_path(&self.dir, address).exists())
+    }
+
+    fn current_block_number(&self) -> u32 {
+        self.block_number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        let name: u64 = rand::random();
+        std::env::temp_dir().join(format!("tanour-{name:x}"))
+    }
+
+    #[test]
+    fn test_pages() {
+        let dir = temp_dir();
+        let address = [1; 21];
+        let chain = FileChain::new(&dir, &address, 4).unwrap();
+        assert_eq!(chain.storage_size().unwrap(), 0);
+        assert!(chain.read_page(1).unwrap().is_empty());
+
+        // The storage file is not created until the pages are written
+        assert!(!chain.exist(&address).unwrap());
+
+        chain.write_page(1, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(chain.read_page(0).unwrap(), vec![0, 0, 0, 0]);
+        assert_eq!(chain.read_page(1).unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(chain.storage_size().unwrap(), 8);
+
+        chain
+            .write_pages(&[(0, vec![5, 6, 7, 8]), (1, Vec::new()), (3, Vec::new())])
+            .unwrap();
+        assert_eq!(
+            chain.read_pages(&[0, 1, 3]).unwrap(),
+            vec![vec![5, 6, 7, 8], vec![0, 0, 0, 0], Vec::new()]
+        );
+        assert_eq!(chain.storage_size().unwrap(), 8);
+
+        // Reopening the storage file
+        let chain = FileChain::new(&dir, &address, 4).unwrap();
+        assert_eq!(chain.read_page(0).unwrap(), vec![5, 6, 7, 8]);
+        assert!(chain.exist(&address).unwrap());
+        assert!(!chain.exist(&[2; 21]).unwrap());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_write_pages_replaces_file() {
+        let dir = temp_dir();
+        let address = [1; 21];
+        let chain = FileChain::new(&dir, &address, 4).unwrap();
+
+        // A temporary file left by a crash is ignored
+        std::fs::write(
+            dir.join(format!("{}.storage.tmp", address_to_hex(&address))),
+            [9; 16],
+        )
+        .unwrap();
+        chain.write_pages(&[(1, vec![1, 2, 3, 4])]).unwrap();
+        assert_eq!(chain.storage_size().unwrap(), 8);
+        assert_eq!(
+            chain.read_pages(&[0, 1]).unwrap(),
+            vec![vec![0, 0, 0, 0], vec![1, 2, 3, 4]]
+        );
+
+        let entries = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(entries, 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_large_file() {
+        let dir = temp_dir();
+        let address = [1; 21];
+        let chain = FileChain::new(&dir, &address, 4).unwrap();
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(storage_path(&dir, &address))
+            .unwrap();
+        file.set_len(u32::MAX as u64 + 1).unwrap();
+        assert!(matches!(
+            chain.storage_size(),
+            Err(Error::ProviderError { .. })
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
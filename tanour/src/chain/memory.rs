@@ -0,0 +1,157 @@
+use crate::blockchain_api::BlockchainAPI;
+use crate::error::{Error, Result};
+use crate::Address;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Debug, Default)]
+struct State {
+    pages: HashMap<u32, Vec<u8>>,
+    accounts: HashSet<Address>,
+    block_number: u32,
+}
+
+/// An in-memory blockchain that keeps the storage pages, accounts and block info.
+///
+/// Clones share the same state, so a clone can be passed to a contract
+/// and the state can be inspected afterward.
+#[derive(Debug, Clone)]
+pub struct InMemoryChain {
+    page_size: u32,
+    state: Arc<Mutex<State>>,
+}
+
+impl InMemoryChain {
+    pub fn new(page_size: u32) -> Self {
+        InMemoryChain {
+            page_size,
+            state: Arc::new(Mutex::new(State::default())),
+        }
+    }
+
+    fn state(&self) -> Result<MutexGuard<'_, State>> {
+        self.state.lock().map_err(|original| Error::ProviderError {
+            msg: format!("{original}"),
+        })
+    }
+
+    /// Adds an account to the chain.
+    pub fn add_account(&self, address: &Address) -> Result<()> {
+        self.state()?.accounts.insert(*address);
+        Ok(())
+    }
+
+    /// Sets the current block number.
+    pub fn set_block_number(&self, block_number: u32) -> Result<()> {
+        self.state()?.block_number = block_number;
+        Ok(())
+    }
+
+    /// Returns the page data. The zero pages return an empty data.
+    pub fn page(&self, page_no: u32) -> Result<Vec<u8>> {
+        Ok(self
+            .state()?
+            .pages
+            .get(&page_no)
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+fn set_page(state: &mut State, page_no: u32, data: &[u8]) {
+    if data.iter().all(|b| *b == 0) {
+        state.pages.remove(&page_no);
+    } else {
+        state.pages.insert(page_no, data.to_vec());
+    }
+}
+
+impl BlockchainAPI for InMemoryChain {
+    fn page_size(&self) -> Result<u32> {
+        Ok(self.page_size)
+    }
+
+    fn storage_size(&self) -> Result<u32> {
+        let state = self.state()?;
+        Ok(state
+            .pages
+            .keys()
+            .max()
+            .map_or(0, |page_no| (page_no + 1) * self.page_size))
+    }
+
+    fn read_page(&self, page_no: u32) -> Result<Vec<u8>> {
+        self.page(page_no)
+    }
+
+    fn read_pages(&self, page_nos: &[u32]) -> Result<Vec<Vec<u8>>> {
+        let state = self.state()?;
+        Ok(page_nos
+            .iter()
+            .map(|page_no| state.pages.get(page_no).cloned().unwrap_or_default())
+            .collect())
+    }
+
+    fn write_page(&self, page_no: u32, data: &[u8]) -> Result<()> {
+        set_page(&mut self.state()?, page_no, data);
+        Ok(())
+    }
+
+    fn write_pages(&self, pages: &[(u32, Vec<u8>)]) -> Result<()> {
+        // Holding the lock makes the write atomic
+        let mut state = self.state()?;
+        for (page_no, data) in pages {
+            set_page(&mut state, *page_no, data);
+        }
+        Ok(())
+    }
+
+    fn exist(&self, address: &Address) -> Result<bool> {
+        Ok(self.state()?.accounts.contains(address))
+    }
+
+    fn current_block_number(&self) -> u32 {
+        self.state().map(|state| state.block_number).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pages() {
+        let chain = InMemoryChain::new(4);
+        assert_eq!(chain.storage_size().unwrap(), 0);
+        assert!(chain.read_page(1).unwrap().is_empty());
+
+        chain.write_page(1, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(chain.read_page(1).unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(chain.storage_size().unwrap(), 8);
+
+        chain
+            .write_pages(&[(0, vec![5, 6, 7, 8]), (1, vec![0, 0, 0, 0])])
+            .unwrap();
+        assert_eq!(
+            chain.read_pages(&[0, 1]).unwrap(),
+            vec![vec![5, 6, 7, 8], Vec::new()]
+        );
+        assert_eq!(chain.storage_size().unwrap(), 4);
+    }
+
+    #[test]
+    fn test_shared_state() {
+        let chain = InMemoryChain::new(4);
+        let cloned = chain.clone();
+        let address = [1; 21];
+
+        cloned.write_page(0, &[1, 2, 3, 4]).unwrap();
+        cloned.add_account(&address).unwrap();
+        cloned.set_block_number(10).unwrap();
+
+        assert_eq!(chain.page(0).unwrap(), vec![1, 2, 3, 4]);
+        assert!(chain.exist(&address).unwrap());
+        assert!(!chain.exist(&[2; 21]).unwrap());
+        assert_eq!(chain.current_block_number(), 10);
+    }
+}
@@ -0,0 +1,7 @@
+mod file;
+mod memory;
+mod replay;
+
+pub use file::*;
+pub use memory::*;
+pub use replay::*;
@@ -0,0 +1,224 @@
+use crate::blockchain_api::BlockchainAPI;
+use crate::error::{Error, Result};
+use crate::trace::{Trace, TraceEvent};
+use crate::{address_from_bytes, address_to_hex, Address, ADDRESS_SIZE};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+#[derive(Debug, Default)]
+struct State {
+    page_size: Option<u32>,
+    storage_size: Option<u32>,
+    pages: HashMap<u32, Vec<u8>>,
+    accounts: HashMap<Address, bool>,
+    block_number: Option<u32>,
+    // The reads that are not in the trace and can't fail
+    missing: Vec<String>,
+}
+
+/// A blockchain that answers the calls from a recorded trace.
+///
+/// The pages are kept as they were read for the first time, and the written pages
+/// replace them, so the replayed execution sees the same storage as the recorded one.
+/// Reading anything that is not in the trace fails. Reading the block number can't fail,
+/// so it is kept and `check` fails afterward.
+///
+/// Clones share the same state, so a clone can be passed to a contract
+/// and the state can be checked afterward.
+#[derive(Debug, Clone)]
+pub struct ReplayChain {
+    state: Arc<Mutex<State>>,
+}
+
+impl ReplayChain {
+    pub fn new(trace: &Trace) -> Self {
+        let mut state = State::default();
+        for event in &trace.events {
+            match event {
+                TraceEvent::PageSize { size } => {
+                    state.page_size.get_or_insert(*size);
+                }
+                TraceEvent::StorageSize { size } => {
+                    state.storage_size.get_or_insert(*size);
+                }
+                TraceEvent::ReadPage { page_no, data } => {
+                    state.pages.entry(*page_no).or_insert_with(|| data.clone());
+                }
+                TraceEvent::ReadPages { page_nos, pages } => {
+                    for (page_no, data) in page_nos.iter().zip(pages) {
+                        state.pages.entry(*page_no).or_insert_with(|| data.clone());
+                    }
+                }
+                TraceEvent::Exist { address, exist } => {
+                    if address.len() == ADDRESS_SIZE {
+                        let address = address_from_bytes(address);
+                        state.accounts.entry(address).or_insert(*exist);
+                    }
+                }
+                TraceEvent::BlockNumber { number } => {
+                    state.block_number.get_or_insert(*number);
+                }
+                _ => {}
+            }
+        }
+
+        ReplayChain {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Fails if the replayed execution read anything that is not in the trace.
+    pub fn check(&self) -> Result<()> {
+        match self.state()?.missing.first() {
+            Some(what) => Err(missing(what.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn state(&self) -> Result<MutexGuard<'_, State>> {
+        self.state.lock().map_err(|original| Error::ProviderError {
+            msg: format!("{original}"),
+        })
+    }
+}
+
+fn missing(what: String) -> Error {
+    Error::ReplayError {
+        msg: format!("{what} is not in the trace"),
+    }
+}
+
+impl BlockchainAPI for ReplayChain {
+    fn page_size(&self) -> Result<u32> {
+        self.state()?
+            .page_size
+            .ok_or_else(|| missing("page size".to_string()))
+    }
+
+    fn storage_size(&self) -> Result<u32> {
+        self.state()?
+            .storage_size
+            .ok_or_else(|| missing("storage size".to_string()))
+    }
+
+    fn read_page(&self, page_no: u32) -> Result<Vec<u8>> {
+        self.state()?
+            .pages
+            .get(&page_no)
+            .cloned()
+            .ok_or_else(|| missing(format!("page {page_no}")))
+    }
+
+    fn read_pages(&self, page_nos: &[u32]) -> Result<Vec<Vec<u8>>> {
+        let state = self.state()?;
+        page_nos
+            .iter()
+            .map(|page_no| {
+                state
+                    .pages
+                    .get(page_no)
+                    .cloned()
+                    .ok_or_else(|| missing(format!("page {page_no}")))
+            })
+            .collect()
+    }
+
+    fn write_page(&self, page_no: u32, data: &[u8]) -> Result<()> {
+        self.state()?.pages.insert(page_no, data.to_vec());
+        Ok(())
+    }
+
+    fn write_pages(&self, pages: &[(u32, Vec<u8>)]) -> Result<()> {
+        let mut state = self.state()?;
+        for (page_no, data) in pages {
+            state.pages.insert(*page_no, data.clone());
+        }
+        Ok(())
+    }
+
+    fn exist(&self, address: &Address) -> Result<bool> {
+        self.state()?
+            .accounts
+            .get(address)
+            .copied()
+            .ok_or_else(|| missing(format!("account {}", address_to_hex(address))))
+    }
+
+    fn current_block_number(&self) -> u32 {
+        let mut state = match self.state() {
+            Ok(state) => state,
+            Err(_) => return 0,
+        };
+        match state.block_number {
+            Some(number) => number,
+            None => {
+                state.missing.push("block number".to_string());
+                0
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay() {
+        let address = [1; 21];
+        let trace = Trace {
+            header: None,
+            events: vec![
+                TraceEvent::PageSize { size: 4 },
+                TraceEvent::StorageSize { size: 8 },
+                TraceEvent::ReadPage {
+                    page_no: 0,
+                    data: vec![1, 2, 3, 4],
+                },
+                TraceEvent::ReadPages {
+                    page_nos: vec![0, 1],
+                    pages: vec![vec![5, 5, 5, 5], vec![6, 7, 8, 9]],
+                },
+                TraceEvent::Exist {
+                    address: address.to_vec(),
+                    exist: true,
+                },
+                TraceEvent::BlockNumber { number: 10 },
+                TraceEvent::BlockNumber { number: 11 },
+            ],
+        };
+        let chain = ReplayChain::new(&trace);
+
+        assert_eq!(chain.page_size().unwrap(), 4);
+        assert_eq!(chain.storage_size().unwrap(), 8);
+        assert_eq!(
+            chain.read_pages(&[0, 1]).unwrap(),
+            vec![vec![1, 2, 3, 4], vec![6, 7, 8, 9]]
+        );
+        assert!(chain.exist(&address).unwrap());
+        assert_eq!(chain.current_block_number(), 10);
+
+        assert!(matches!(chain.read_page(2), Err(Error::ReplayError { .. })));
+        assert!(matches!(
+            chain.exist(&[2; 21]),
+            Err(Error::ReplayError { .. })
+        ));
+
+        chain.write_page(2, &[0, 0, 0, 1]).unwrap();
+        assert_eq!(chain.read_page(2).unwrap(), vec![0, 0, 0, 1]);
+        assert!(chain.check().is_ok());
+    }
+
+    #[test]
+    fn test_missing_block_number() {
+        let trace = Trace {
+            header: None,
+            events: Vec::new(),
+        };
+        let chain = ReplayChain::new(&trace);
+        assert!(chain.check().is_ok());
+
+        assert_eq!(chain.current_block_number(), 0);
+        assert!(matches!(chain.check(), Err(Error::ReplayError { .. })));
+    }
+}
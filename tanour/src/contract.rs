@@ -1,11 +1,20 @@
+#[cfg(feature = "codec")]
+use crate::abi::Abi;
 use crate::blockchain_api::BlockchainAPI;
-use crate::error::Result;
+use crate::cache::ModuleCache;
+use crate::error::{Error, Result};
 use crate::executor::Executor;
+use crate::gas::GasSchedule;
 use crate::memory::Pointer;
+use crate::profile::Profile;
 use crate::provider::ProviderAdaptor;
-use crate::{wasmer, Address};
+use crate::trace::{RecordingAPI, Trace, TraceEvent, TraceHeader, TraceRecorder};
+use crate::{address_to_hex, contract_address, wasmer, Address};
 
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub use crate::page::StorageDiff;
 
 #[derive(Debug)]
 pub struct ResultData {
@@ -13,22 +22,48 @@ pub struct ResultData {
     pub data: Vec<u8>,
 }
 
+/// The information about compiling the contract code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompileInfo {
+    /// The time spent on compiling the code, or loading the module from the cache
+    pub duration: Duration,
+    /// The module is loaded from the cache
+    pub cache_hit: bool,
+}
+
 // TODO: rename me, it is confusing with ExecuteParams
 #[derive(Debug)]
 pub struct Params {
     pub memory_limit_page: u32,
     pub metering_limit: u64,
+    pub stack_height_limit: u32,
+    pub storage_limit: u32,
+    pub gas_schedule: GasSchedule,
+    /// The cache for the compiled modules, the code is compiled on each execution if it is not set.
+    pub module_cache: Option<Arc<ModuleCache>>,
+    /// Records the execution trace, if it is set.
+    pub trace: Option<TraceRecorder>,
+    /// Measures the metering points that each function consumes.
+    pub profiling: bool,
 }
 
 pub struct Contract {
     // Wasm executor
     executor: Box<dyn Executor>,
     // State of the contract
-    _state: Arc<Mutex<ProviderAdaptor>>,
+    state: Arc<Mutex<ProviderAdaptor>>,
     // Contract's address
-    _address: Address,
+    address: Address,
+    // Contract's ABI, if it is embedded in the code
+    #[cfg(feature = "codec")]
+    abi: Result<Option<Abi>>,
+    // Records the execution trace, if it is set
+    recorder: Option<TraceRecorder>,
 }
 
+/// The functions that a contract should export.
+const REQUIRED_EXPORTS: [&str; 5] = ["instantiate", "process", "query", "allocate", "deallocate"];
+
 impl Contract {
     pub fn new(
         api: Box<dyn BlockchainAPI>,
@@ -36,22 +71,103 @@ impl Contract {
         code: &[u8],
         params: Params,
     ) -> Result<Self> {
-        let provider = Arc::new(Mutex::new(ProviderAdaptor::new(api)?));
-        let executor = wasmer::WasmerExecutor::new(
-            code,
-            params.memory_limit_page,
-            params.metering_limit,
-            provider.clone(),
-        )?;
+        let api: Box<dyn BlockchainAPI> = match &params.trace {
+            Some(recorder) => {
+                recorder.set_header(TraceHeader::new(address, code, &params));
+                Box::new(RecordingAPI::new(api, recorder.clone()))
+            }
+            None => api,
+        };
+        let provider = Arc::new(Mutex::new(ProviderAdaptor::new(api, params.storage_limit)?));
+        let executor = wasmer::WasmerExecutor::new(code, &params, provider.clone())?;
+        // The ABI is optional. A malformed ABI doesn't prevent executing a deployed contract,
+        // but it is reported when the ABI is used.
+        #[cfg(feature = "codec")]
+        let abi = Abi::from_code(code);
 
         Ok(Contract {
             executor: Box::new(executor),
-            _state: provider,
-            _address: *address,
+            state: provider,
+            address: *address,
+            #[cfg(feature = "codec")]
+            abi,
+            recorder: params.trace,
         })
     }
 
+    /// Creates a new contract for deployment.
+    /// The contract address is derived from the sender, the code and the salt.
+    /// The code is validated to export all the required functions and to have a valid ABI, if any.
+    /// The contract should be instantiated by calling `call_instantiate` afterward.
+    /// It fails if a contract already exists at the derived address.
+    pub fn deploy(
+        api: Box<dyn BlockchainAPI>,
+        sender: &Address,
+        salt: &[u8],
+        code: &[u8],
+        params: Params,
+    ) -> Result<Self> {
+        let address = contract_address(sender, code, salt);
+        if api.exist(&address)? {
+            return Err(Error::ContractExists {
+                address: address_to_hex(&address),
+            });
+        }
+        let contract = Contract::new(api, &address, code, params)?;
+
+        // A new contract can't be deployed with a malformed ABI
+        #[cfg(feature = "codec")]
+        contract.abi()?;
+
+        for name in REQUIRED_EXPORTS {
+            if !contract.executor.has_function(name) {
+                return Err(Error::MissingExport {
+                    name: name.to_string(),
+                });
+            }
+        }
+
+        Ok(contract)
+    }
+
+    pub fn address(&self) -> &Address {
+        &self.address
+    }
+
+    /// Returns the ABI of the contract, if it is embedded in the code.
+    /// It fails if the ABI section is malformed.
+    #[cfg(feature = "codec")]
+    pub fn abi(&self) -> Result<Option<&Abi>> {
+        match &self.abi {
+            Ok(abi) => Ok(abi.as_ref()),
+            Err(Error::AbiError { msg }) => Err(Error::AbiError { msg: msg.clone() }),
+            Err(err) => Err(Error::AbiError {
+                msg: format!("{err}"),
+            }),
+        }
+    }
+
+    #[tracing::instrument(
+        level = "debug",
+        skip(self, data),
+        fields(address = %address_to_hex(&self.address), data_size = data.len())
+    )]
     fn call_exported_fn(&mut self, fname: &str, data: &[u8]) -> Result<Vec<u8>> {
+        self.record(TraceEvent::Call {
+            function: fname.to_string(),
+            arg: data.to_vec(),
+        });
+        let result = self.call_and_read(fname, data);
+        self.record(TraceEvent::Return {
+            function: fname.to_string(),
+            data: result.as_ref().cloned().unwrap_or_default(),
+            error: result.as_ref().err().map(|err| format!("{err}")),
+            consumed_points: self.executor.consumed_points().unwrap_or_default(),
+        });
+        result
+    }
+
+    fn call_and_read(&mut self, fname: &str, data: &[u8]) -> Result<Vec<u8>> {
         let size = data.len() as u32;
         let ptr_64 = self.allocate(size)?;
         let ptr = Pointer::from_u64(ptr_64);
@@ -77,6 +193,17 @@ impl Contract {
         self.call_exported_fn("query", encoded_arg)
     }
 
+    fn record(&self, event: TraceEvent) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(event);
+        }
+    }
+
+    /// Returns the recorded trace, if the trace is enabled in the params.
+    pub fn trace(&self) -> Option<Trace> {
+        self.recorder.as_ref().map(TraceRecorder::trace)
+    }
+
     fn allocate(&self, size: u32) -> Result<u64> {
         self.executor.call_fn_2("allocate", size)
     }
@@ -89,6 +216,13 @@ impl Contract {
         self.executor.remaining_points()
     }
 
+    /// Sets the points that the execution is given.
+    /// They are capped by the metering limit in the params,
+    /// which is compiled into the module and stays the same for all the executions.
+    pub fn set_gas_limit(&self, gas: u64) -> Result<()> {
+        self.executor.set_remaining_points(gas)
+    }
+
     pub fn consumed_points(&self) -> Result<u64> {
         self.executor.consumed_points()
     }
@@ -96,4 +230,50 @@ impl Contract {
     pub fn exhausted(&self) -> Result<bool> {
         self.executor.exhausted()
     }
+
+    pub fn compile_info(&self) -> CompileInfo {
+        self.executor.compile_info()
+    }
+
+    /// Returns the gas profile of the functions, if profiling is enabled in the params.
+    pub fn profile(&self) -> Result<Option<Profile>> {
+        self.executor.profile()
+    }
+
+    /// Returns the storage pages that are updated by the contract, sorted by the page number.
+    pub fn updated_pages(&self) -> Result<Vec<(u32, Vec<u8>)>> {
+        let state = self.state.lock().map_err(|original| Error::RuntimeError {
+            msg: format!("{original}"),
+        })?;
+
+        Ok(state.updated_pages())
+    }
+
+    /// Returns the changed byte ranges of the storage, sorted by the offset.
+    pub fn storage_diffs(&self) -> Result<Vec<StorageDiff>> {
+        let state = self.state.lock().map_err(|original| Error::RuntimeError {
+            msg: format!("{original}"),
+        })?;
+
+        Ok(state.storage_diffs())
+    }
+
+    /// Writes the updated storage pages back to the blockchain.
+    /// All the updated pages are written in one batch, so they can be applied atomically.
+    pub fn commit(&self) -> Result<()> {
+        let mut state = self.state.lock().map_err(|original| Error::RuntimeError {
+            msg: format!("{original}"),
+        })?;
+
+        state.commit()
+    }
+
+    /// Returns the number of bytes that the storage has grown by the contract.
+    pub fn storage_growth(&self) -> Result<u32> {
+        let state = self.state.lock().map_err(|original| Error::RuntimeError {
+            msg: format!("{original}"),
+        })?;
+
+        Ok(state.storage_growth())
+    }
 }
@@ -1,5 +1,62 @@
 use thiserror::Error;
 
+/// The reason that the Wasm execution trapped.
+/// The numeric values are stable and can be shared with the clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum TrapCode {
+    StackOverflow = 0,
+    HeapAccessOutOfBounds = 1,
+    HeapMisaligned = 2,
+    TableAccessOutOfBounds = 3,
+    IndirectCallToNull = 4,
+    BadSignature = 5,
+    IntegerOverflow = 6,
+    IntegerDivisionByZero = 7,
+    BadConversionToInteger = 8,
+    UnreachableCodeReached = 9,
+    UnalignedAtomic = 10,
+}
+
+impl TrapCode {
+    /// Returns the stable numeric code of the trap.
+    pub fn code(&self) -> u32 {
+        *self as u32
+    }
+}
+
+/// A frame of the Wasm call stack when the execution trapped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    /// The index of the function in the module
+    pub func_index: u32,
+    /// The name of the function in the name section, if the module has it
+    pub func_name: Option<String>,
+    /// The offset of the instruction from the start of the module
+    pub module_offset: usize,
+    /// The offset of the instruction from the start of the function
+    pub func_offset: usize,
+}
+
+impl std::fmt::Display for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.func_name {
+            Some(name) => write!(f, "{name} (func[{}])", self.func_index)?,
+            None => write!(f, "func[{}]", self.func_index)?,
+        }
+        write!(f, " @ {:#x}", self.module_offset)
+    }
+}
+
+/// Formats the frames as a backtrace, one frame per line and the innermost frame comes first.
+pub fn backtrace(frames: &[Frame]) -> String {
+    frames
+        .iter()
+        .enumerate()
+        .map(|(index, frame)| format!("  {index}: {frame}\n"))
+        .collect()
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Instantiating error: {msg}")]
@@ -11,6 +68,9 @@ pub enum Error {
     #[error("Runtime error: {msg}")]
     RuntimeError { msg: String },
 
+    #[error("Stack overflow: stack height exceeded {limit}")]
+    StackOverflow { limit: u32 },
+
     #[error("Memory error: {msg}")]
     MemoryError { msg: String },
 
@@ -19,5 +79,121 @@ pub enum Error {
 
     #[error("NetworkError error: {}", msg)]
     NetworkError { msg: String },
+
+    #[error("Out of gas: metering limit {limit} is exhausted")]
+    OutOfGas { limit: u64 },
+
+    #[error("Trap: {code:?}, {msg}")]
+    Trap {
+        code: TrapCode,
+        msg: String,
+        /// The call stack when the execution trapped, the innermost frame comes first
+        frames: Vec<Frame>,
+    },
+
+    #[error("Missing export: {name}")]
+    MissingExport { name: String },
+
+    #[error("Invalid return type for {name}")]
+    InvalidReturnType { name: String },
+
+    #[error("Storage out of bounds: offset {offset}, length {length}")]
+    StorageOutOfBounds { offset: u32, length: u32 },
+
+    #[error("Provider error: {msg}")]
+    ProviderError { msg: String },
+
+    #[error("Timeout: {msg}")]
+    Timeout { msg: String },
+
+    #[error("Codec error: {msg}")]
+    CodecError { msg: String },
+
+    #[error("ABI error: {msg}")]
+    AbiError { msg: String },
+
+    #[error("Replay error: {msg}")]
+    ReplayError { msg: String },
+
+    #[error("Contract already exists: {address}")]
+    ContractExists { address: String },
 }
+
+impl Error {
+    /// Returns the stable numeric code of the error.
+    /// These codes are shared with the clients and should never change.
+    pub fn code(&self) -> u32 {
+        match self {
+            Error::InstantiationError { .. } => 1,
+            Error::CompileError { .. } => 2,
+            Error::RuntimeError { .. } => 3,
+            Error::StackOverflow { .. } => 4,
+            Error::MemoryError { .. } => 5,
+            Error::IOError(_) => 6,
+            Error::NetworkError { .. } => 7,
+            Error::OutOfGas { .. } => 8,
+            Error::Trap { .. } => 9,
+            Error::MissingExport { .. } => 10,
+            Error::InvalidReturnType { .. } => 11,
+            Error::StorageOutOfBounds { .. } => 12,
+            Error::ProviderError { .. } => 13,
+            Error::Timeout { .. } => 14,
+            Error::CodecError { .. } => 15,
+            Error::AbiError { .. } => 16,
+            Error::ReplayError { .. } => 17,
+            Error::ContractExists { .. } => 18,
+        }
+    }
+
+    /// Returns the call stack when the execution trapped, or an empty list for the other errors.
+    pub fn frames(&self) -> &[Frame] {
+        match self {
+            Error::Trap { frames, .. } => frames,
+            _ => &[],
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(feature = "codec")]
+impl From<tanour_codec::Error> for Error {
+    fn from(original: tanour_codec::Error) -> Self {
+        match original {
+            tanour_codec::Error::CodecError { msg } => Error::CodecError { msg },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trap_display() {
+        let err = Error::Trap {
+            code: TrapCode::UnreachableCodeReached,
+            msg: "unreachable".to_string(),
+            frames: vec![
+                Frame {
+                    func_index: 3,
+                    func_name: Some("divide".to_string()),
+                    module_offset: 0x1a2,
+                    func_offset: 0x12,
+                },
+                Frame {
+                    func_index: 5,
+                    func_name: None,
+                    module_offset: 0x2b0,
+                    func_offset: 0x8,
+                },
+            ],
+        };
+        assert_eq!(err.to_string(), "Trap: UnreachableCodeReached, unreachable");
+        assert_eq!(
+            backtrace(err.frames()),
+            "  0: divide (func[3]) @ 0x1a2\n  1: func[5] @ 0x2b0\n"
+        );
+        assert!(Error::OutOfGas { limit: 1 }.frames().is_empty());
+    }
+}
@@ -1,6 +1,9 @@
-use crate::{error::Result, memory::Pointer};
+use crate::{contract::CompileInfo, error::Result, memory::Pointer, profile::Profile};
 
 pub trait Executor {
+    /// Checks if the function is exported by the module.
+    fn has_function(&self, name: &str) -> bool;
+
     /// Calls a function with the given arguments.
     fn call_fn_0(&self, name: &str, arg: u64) -> Result<()>;
 
@@ -19,9 +22,18 @@ pub trait Executor {
     // Get the remaining points (metering)
     fn remaining_points(&self) -> Result<u64>;
 
+    // Set the remaining points (metering), capped by the metering limit of the module
+    fn set_remaining_points(&self, points: u64) -> Result<()>;
+
     // Get the consumed points (metering)
     fn consumed_points(&self) -> Result<u64>;
 
     // Check if all points are consumed (metering)
     fn exhausted(&self) -> Result<bool>;
+
+    // Get the information about compiling the code
+    fn compile_info(&self) -> CompileInfo;
+
+    // Get the gas profile of the functions, if profiling is enabled
+    fn profile(&self) -> Result<Option<Profile>>;
 }
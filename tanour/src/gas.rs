@@ -0,0 +1,59 @@
+//! The gas schedule, the metering points that each Wasm operator consumes.
+
+use serde::{Deserialize, Serialize};
+use wasmer::wasmparser::Operator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GasSchedule {
+    /// The cost of the operators that have no specific cost
+    pub default_cost: u64,
+    /// The cost of the direct and indirect function calls
+    pub call_cost: u64,
+    /// The cost of growing the memory
+    pub memory_grow_cost: u64,
+}
+
+impl Default for GasSchedule {
+    fn default() -> Self {
+        GasSchedule {
+            default_cost: 1,
+            call_cost: 1,
+            memory_grow_cost: 1,
+        }
+    }
+}
+
+impl GasSchedule {
+    /// Returns the metering points that the operator consumes.
+    pub(crate) fn cost(&self, operator: &Operator) -> u64 {
+        match operator {
+            Operator::Call { .. } | Operator::CallIndirect { .. } => self.call_cost,
+            Operator::MemoryGrow { .. } => self.memory_grow_cost,
+            _ => self.default_cost,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost() {
+        let schedule = GasSchedule {
+            default_cost: 1,
+            call_cost: 10,
+            memory_grow_cost: 100,
+        };
+        assert_eq!(schedule.cost(&Operator::Nop), 1);
+        assert_eq!(schedule.cost(&Operator::Call { function_index: 0 }), 10);
+        assert_eq!(
+            schedule.cost(&Operator::MemoryGrow {
+                mem: 0,
+                mem_byte: 0
+            }),
+            100
+        );
+    }
+}
@@ -1,6 +1,16 @@
+use blake2::digest::{Update, VariableOutput};
+use blake2::VarBlake2b;
+
+#[cfg(feature = "codec")]
+pub mod abi;
 pub mod blockchain_api;
+pub mod cache;
+pub mod chain;
 pub mod contract;
 pub mod error;
+pub mod gas;
+pub mod profile;
+pub mod trace;
 
 mod executor;
 mod memory;
@@ -8,10 +18,16 @@ mod page;
 mod provider;
 mod wasmer;
 
+#[cfg(feature = "codec")]
+pub use tanour_codec as codec;
+
 pub const ADDRESS_SIZE: usize = 21;
 
 pub type Address = [u8; ADDRESS_SIZE];
 
+/// The type of the contract addresses. It is stored in the first byte of the address.
+pub const CONTRACT_ADDRESS_TYPE: u8 = 4;
+
 pub fn address_from_bytes(d: &[u8]) -> Address {
     let mut addr: Address = [0u8; ADDRESS_SIZE];
     addr.copy_from_slice(d);
@@ -29,3 +45,21 @@ pub fn address_from_hex(s: &str) -> Address {
 pub fn address_to_hex(addr: &Address) -> String {
     hex::encode(addr)
 }
+
+/// Derives a deterministic contract address from the sender address, the code and the salt.
+/// The same code can be deployed by the same sender in different addresses by changing the salt.
+pub fn contract_address(sender: &Address, code: &[u8], salt: &[u8]) -> Address {
+    let mut code_hasher = VarBlake2b::new(32).unwrap();
+    code_hasher.update(code);
+    let code_hash = code_hasher.finalize_boxed();
+
+    let mut hasher = VarBlake2b::new(ADDRESS_SIZE - 1).unwrap();
+    hasher.update(sender);
+    hasher.update(code_hash);
+    hasher.update(salt);
+
+    let mut addr: Address = [0u8; ADDRESS_SIZE];
+    addr[0] = CONTRACT_ADDRESS_TYPE;
+    hasher.finalize_variable(|res| addr[1..].copy_from_slice(res));
+    addr
+}
@@ -1,17 +1,198 @@
+/// A changed byte range of the storage.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageDiff {
+    /// The offset of the range in the storage
+    pub offset: u32,
+    /// The bytes before the execution
+    pub old_data: Vec<u8>,
+    /// The bytes after the execution
+    pub new_data: Vec<u8>,
+}
+
+/// A page of the contract's storage.
+///
+/// The zero pages (the pages that all bytes are zero) are kept without data,
+/// and the data is allocated on the first write.
 #[derive(Debug)]
 pub struct Page {
     pub offset: u32,
     pub length: u32,
-    pub data: Vec<u8>,
     pub updated: bool,
+    data: Vec<u8>,
+    // True if the page was a zero page when it was read
+    was_zero: bool,
+    // The page data before the first write, it is kept to calculate the diffs
+    original: Option<Vec<u8>>,
 }
 impl Page {
+    /// Creates a new page. An empty data defines a zero page.
     pub fn new(offset: u32, length: u32, data: Vec<u8>) -> Self {
+        let was_zero = data.iter().all(|b| *b == 0);
+        let data = if was_zero { Vec::new() } else { data };
+
         Page {
             offset,
             length,
             data,
             updated: false,
+            was_zero,
+            original: None,
+        }
+    }
+
+    /// Returns true if all the bytes in the page are zero.
+    pub fn is_zero(&self) -> bool {
+        self.data.iter().all(|b| *b == 0)
+    }
+
+    /// Returns true if the page should be written back to the storage.
+    /// The zero pages that remain zero don't need to be written back.
+    pub fn is_dirty(&self) -> bool {
+        self.updated && !(self.was_zero && self.is_zero())
+    }
+
+    /// Marks the page as written back to the storage.
+    pub fn commit(&mut self) {
+        self.updated = false;
+        self.was_zero = self.is_zero();
+        self.original = None;
+    }
+
+    /// Returns the changed byte ranges of the page since the first write.
+    pub fn diffs(&self) -> Vec<StorageDiff> {
+        let original = match &self.original {
+            Some(original) => original,
+            None => return Vec::new(),
+        };
+
+        // Zero pages have no data
+        let old_at = |i: usize| original.get(i).copied().unwrap_or(0);
+        let new_at = |i: usize| self.data.get(i).copied().unwrap_or(0);
+
+        let mut diffs = Vec::new();
+        let mut i = 0;
+        while i < self.length as usize {
+            if old_at(i) == new_at(i) {
+                i += 1;
+                continue;
+            }
+
+            let start = i;
+            while i < self.length as usize && old_at(i) != new_at(i) {
+                i += 1;
+            }
+
+            diffs.push(StorageDiff {
+                offset: self.offset + start as u32,
+                old_data: (start..i).map(old_at).collect(),
+                new_data: (start..i).map(new_at).collect(),
+            });
+        }
+
+        diffs
+    }
+
+    /// Returns the page data. The zero pages return an empty data.
+    pub fn data(&self) -> Vec<u8> {
+        if self.is_zero() {
+            Vec::new()
+        } else {
+            self.data.clone()
         }
     }
+
+    /// Reads `len` bytes from the `offset` of the page and appends them to `buf`.
+    pub fn read(&self, offset: u32, len: u32, buf: &mut Vec<u8>) {
+        let start = offset as usize;
+        let end = (offset + len) as usize;
+        if self.data.is_empty() {
+            buf.resize(buf.len() + (end - start), 0);
+        } else {
+            buf.extend_from_slice(&self.data[start..end]);
+        }
+    }
+
+    /// Writes `data` into the `offset` of the page.
+    pub fn write(&mut self, offset: u32, data: &[u8]) {
+        if self.original.is_none() {
+            self.original = Some(self.data.clone());
+        }
+        if self.data.is_empty() {
+            self.data = vec![0; self.length as usize];
+        }
+        let start = offset as usize;
+        self.data[start..start + data.len()].copy_from_slice(data);
+        self.updated = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zero_page() {
+        let mut page = Page::new(0, 8, Vec::new());
+        assert!(page.is_zero());
+        assert!(!page.is_dirty());
+
+        let mut buf = Vec::new();
+        page.read(2, 4, &mut buf);
+        assert_eq!(buf, vec![0; 4]);
+
+        page.write(2, &[0, 0]);
+        assert!(page.updated);
+        assert!(!page.is_dirty());
+        assert!(page.data().is_empty());
+
+        page.write(2, &[1, 2]);
+        assert!(page.is_dirty());
+        assert_eq!(page.data(), vec![0, 0, 1, 2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_clear_page() {
+        let mut page = Page::new(0, 4, vec![1, 2, 3, 4]);
+        assert!(!page.is_zero());
+
+        page.write(0, &[0, 0, 0, 0]);
+        assert!(page.is_zero());
+        assert!(page.is_dirty());
+        assert!(page.data().is_empty());
+    }
+
+    #[test]
+    fn test_diffs() {
+        let mut page = Page::new(8, 8, Vec::new());
+        assert!(page.diffs().is_empty());
+
+        page.write(1, &[1, 2]);
+        page.write(3, &[0, 0, 5]);
+        page.write(7, &[7]);
+
+        let diffs = page.diffs();
+        assert_eq!(
+            diffs,
+            vec![
+                StorageDiff {
+                    offset: 9,
+                    old_data: vec![0, 0],
+                    new_data: vec![1, 2],
+                },
+                StorageDiff {
+                    offset: 13,
+                    old_data: vec![0],
+                    new_data: vec![5],
+                },
+                StorageDiff {
+                    offset: 15,
+                    old_data: vec![0],
+                    new_data: vec![7],
+                },
+            ]
+        );
+
+        page.commit();
+        assert!(page.diffs().is_empty());
+    }
 }
@@ -0,0 +1,154 @@
+//! The gas profile of the contract, the metering points that each function consumes.
+//!
+//! The inclusive points of a function include the points consumed by its callees,
+//! and the exclusive points are only consumed by the function itself.
+//! The exclusive points are also kept by the callers of the function,
+//! a limited number of callers are tracked for each function.
+
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionProfile {
+    /// The index of the function in the module
+    pub index: u32,
+    /// The name of the function in the name section, or `func[<index>]` if it has no name
+    pub name: String,
+    /// The number of calls
+    pub calls: u64,
+    /// The points consumed by the function and its callees
+    pub inclusive: u64,
+    /// The points consumed by the function itself
+    pub exclusive: u64,
+    /// The points consumed by the function itself, by its callers.
+    /// The points of the calls from the host are not included.
+    pub callers: Vec<CallerProfile>,
+    /// The points consumed by the function itself, by the callers that are not tracked
+    pub other_callers: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallerProfile {
+    /// The index of the caller function in the module
+    pub index: u32,
+    /// The points consumed by the function itself, when it is called by this caller
+    pub exclusive: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    pub functions: Vec<FunctionProfile>,
+}
+
+impl Profile {
+    /// Returns the profile of the function by its name.
+    pub fn function(&self, name: &str) -> Option<&FunctionProfile> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    /// Returns a table of the functions, sorted by the inclusive points.
+    pub fn report(&self) -> String {
+        let mut functions: Vec<_> = self.functions.iter().collect();
+        functions.sort_by(|a, b| b.inclusive.cmp(&a.inclusive).then(a.index.cmp(&b.index)));
+
+        let mut report = format!(
+            "{:<40} {:>10} {:>12} {:>12}\n",
+            "function", "calls", "inclusive", "exclusive"
+        );
+        for function in functions {
+            let _ = writeln!(
+                report,
+                "{:<40} {:>10} {:>12} {:>12}",
+                function.name, function.calls, function.inclusive, function.exclusive
+            );
+        }
+        report
+    }
+
+    /// Returns the exclusive points in the folded stacks format, used by the flamegraph tools.
+    /// Each stack has the caller and the function, or only the function if it is called by the host.
+    /// The callers that are not tracked are written as `[others]`.
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for function in &self.functions {
+            let mut host_points = function.exclusive;
+            for caller in &function.callers {
+                host_points = host_points.saturating_sub(caller.exclusive);
+                let caller_name = match self.functions.iter().find(|f| f.index == caller.index) {
+                    Some(caller) => frame_name(&caller.name),
+                    None => format!("func[{}]", caller.index),
+                };
+                let _ = writeln!(
+                    folded,
+                    "{caller_name};{} {}",
+                    frame_name(&function.name),
+                    caller.exclusive
+                );
+            }
+            if function.other_callers > 0 {
+                host_points = host_points.saturating_sub(function.other_callers);
+                let _ = writeln!(
+                    folded,
+                    "[others];{} {}",
+                    frame_name(&function.name),
+                    function.other_callers
+                );
+            }
+            if host_points > 0 {
+                let _ = writeln!(folded, "{} {host_points}", frame_name(&function.name));
+            }
+        }
+        folded
+    }
+}
+
+/// Returns the name of the function as a frame of the folded stacks.
+fn frame_name(name: &str) -> String {
+    // Semicolons separate the frames and spaces separate the points
+    name.replace(';', ":").replace(' ', "_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let profile = Profile {
+            functions: vec![
+                FunctionProfile {
+                    index: 1,
+                    name: "inner".to_string(),
+                    calls: 4,
+                    inclusive: 7,
+                    exclusive: 7,
+                    callers: vec![CallerProfile {
+                        index: 2,
+                        exclusive: 4,
+                    }],
+                    other_callers: 1,
+                },
+                FunctionProfile {
+                    index: 2,
+                    name: "outer fn".to_string(),
+                    calls: 1,
+                    inclusive: 8,
+                    exclusive: 4,
+                    callers: Vec::new(),
+                    other_callers: 0,
+                },
+            ],
+        };
+
+        let report = profile.report();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("outer fn"));
+        assert!(lines[2].starts_with("inner"));
+
+        assert_eq!(
+            profile.folded(),
+            "outer_fn;inner 4\n[others];inner 1\ninner 2\nouter_fn 4\n"
+        );
+        assert_eq!(profile.function("outer fn").unwrap().inclusive, 8);
+    }
+}
@@ -1,8 +1,8 @@
 use crate::blockchain_api::BlockchainAPI;
-use crate::error::Result;
-use crate::page::Page;
+use crate::error::{Error, Result};
+use crate::page::{Page, StorageDiff};
 
-use std::collections::{hash_map::Entry, HashMap};
+use std::collections::HashMap;
 
 #[cfg(test)]
 use mockall::{automock, predicate::*};
@@ -17,42 +17,168 @@ pub struct ProviderAdaptor {
     api: Box<dyn BlockchainAPI>,
     page_size: u32,
     pages: HashMap<u32, Page>,
+    // The maximum size of the storage in bytes
+    storage_limit: u32,
+    // The size of the storage before the execution
+    storage_size: u32,
+    // The end offset of the furthest write
+    written_end: u32,
 }
 
 impl ProviderAdaptor {
-    pub fn new(api: Box<dyn BlockchainAPI>) -> Result<Self> {
+    pub fn new(api: Box<dyn BlockchainAPI>, storage_limit: u32) -> Result<Self> {
         Ok(ProviderAdaptor {
             page_size: api.page_size()?,
             pages: HashMap::new(),
+            storage_limit,
+            storage_size: api.storage_size()?,
+            written_end: 0,
             api,
         })
     }
-    fn read_page(&mut self, page_no: u32) -> Result<&mut Page> {
-        println!("fn: read_page, page_no: {page_no}");
-        let offset = page_no * self.page_size;
 
-        let page = match self.pages.entry(page_no) {
-            Entry::Occupied(o) => o.into_mut(),
-            Entry::Vacant(v) => {
-                println!(
-                    "Try to read the storage. offset: {offset}, page_size: {}",
+    /// Checks the given range is inside the storage limit and returns the end offset.
+    fn check_bounds(&self, offset: u32, length: u32) -> Result<u32> {
+        match offset.checked_add(length) {
+            Some(end) if end <= self.storage_limit => Ok(end),
+            _ => Err(Error::StorageOutOfBounds { offset, length }),
+        }
+    }
+
+    /// Makes a page from the bytes that are read from the blockchain.
+    fn make_page(&self, page_no: u32, bytes: Vec<u8>) -> Result<Page> {
+        // An empty data means a zero page
+        if !bytes.is_empty() && bytes.len() != self.page_size as usize {
+            return Err(Error::ProviderError {
+                msg: format!(
+                    "invalid page size for page {page_no}: {}, expected: {}",
+                    bytes.len(),
                     self.page_size
-                );
-                let bytes = self.api.read_page(page_no)?;
-                let page = Page::new(offset, self.page_size, bytes);
-                v.insert(page)
+                ),
+            });
+        }
+        let offset = page_no * self.page_size;
+        Ok(Page::new(offset, self.page_size, bytes))
+    }
+
+    fn read_page(&mut self, page_no: u32) -> Result<&mut Page> {
+        let offset = page_no.saturating_mul(self.page_size);
+        if offset >= self.storage_limit {
+            return Err(Error::StorageOutOfBounds {
+                offset,
+                length: self.page_size,
+            });
+        }
+
+        if !self.pages.contains_key(&page_no) {
+            tracing::debug!(page_no, "reading the page");
+            let bytes = self.api.read_page(page_no)?;
+            let page = self.make_page(page_no, bytes)?;
+            self.pages.insert(page_no, page);
+        }
+
+        Ok(self.pages.get_mut(&page_no).unwrap())
+    }
+
+    /// Fetches the missing pages in the given range in one batch.
+    /// Pages are checked against the storage limit before calling this function.
+    fn prefetch_pages(&mut self, first_page: u32, last_page: u32) -> Result<()> {
+        let missing: Vec<u32> = (first_page..last_page + 1)
+            .filter(|page_no| !self.pages.contains_key(page_no))
+            .collect();
+
+        // A single page is fetched on demand.
+        if missing.len() < 2 {
+            return Ok(());
+        }
+
+        tracing::debug!(pages = ?missing, "reading the pages");
+        let pages = self.api.read_pages(&missing)?;
+        if pages.len() != missing.len() {
+            return Err(Error::ProviderError {
+                msg: format!(
+                    "invalid number of pages: {}, expected: {}",
+                    pages.len(),
+                    missing.len()
+                ),
+            });
+        }
+
+        for (page_no, bytes) in missing.into_iter().zip(pages) {
+            let page = self.make_page(page_no, bytes)?;
+            self.pages.insert(page_no, page);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the pages that are updated during the execution, sorted by the page number.
+    /// The zero pages have an empty data and the zero pages that remain zero are skipped.
+    pub fn updated_pages(&self) -> Vec<(u32, Vec<u8>)> {
+        let mut pages: Vec<(u32, Vec<u8>)> = self
+            .pages
+            .iter()
+            .filter(|(_, page)| page.is_dirty())
+            .map(|(page_no, page)| (*page_no, page.data()))
+            .collect();
+        pages.sort_by_key(|(page_no, _)| *page_no);
+        pages
+    }
+
+    /// Returns the changed byte ranges of the storage, sorted by the offset.
+    /// The adjacent ranges in the consecutive pages are merged.
+    pub fn storage_diffs(&self) -> Vec<StorageDiff> {
+        let mut page_nos: Vec<&u32> = self.pages.keys().collect();
+        page_nos.sort();
+
+        let mut diffs: Vec<StorageDiff> = Vec::new();
+        for page_no in page_nos {
+            for diff in self.pages[page_no].diffs() {
+                match diffs.last_mut() {
+                    Some(last) if last.offset + last.new_data.len() as u32 == diff.offset => {
+                        last.old_data.extend(diff.old_data);
+                        last.new_data.extend(diff.new_data);
+                    }
+                    _ => diffs.push(diff),
+                }
             }
-        };
+        }
+
+        diffs
+    }
+
+    /// Writes all the updated pages back to the blockchain in one batch.
+    pub fn commit(&mut self) -> Result<()> {
+        let pages = self.updated_pages();
+        if pages.is_empty() {
+            return Ok(());
+        }
 
-        Ok(page)
+        self.api.write_pages(&pages)?;
+        for page in self.pages.values_mut() {
+            page.commit();
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of bytes that the storage has grown during the execution.
+    pub fn storage_growth(&self) -> u32 {
+        self.written_end.saturating_sub(self.storage_size)
     }
 }
 
 impl Provider for ProviderAdaptor {
     fn read_storage(&mut self, offset: u32, length: u32) -> Result<Vec<u8>> {
-        println!("fn: read_storage, offset: {offset}, length: {length}");
+        let end = self.check_bounds(offset, length)?;
+        if length == 0 {
+            return Ok(Vec::new());
+        }
+
         let first_page = offset / self.page_size;
-        let last_page = (offset + length) / self.page_size;
+        let last_page = (end - 1) / self.page_size;
+        self.prefetch_pages(first_page, last_page)?;
+
         let mut data = Vec::new();
         let mut read_offset = offset % self.page_size;
         let mut read_length = 0;
@@ -64,7 +190,7 @@ impl Provider for ProviderAdaptor {
             }
 
             let page = self.read_page(page_no)?;
-            data.extend_from_slice(&page.data[read_offset as usize..(read_offset + len) as usize]);
+            page.read(read_offset, len, &mut data);
 
             read_offset = 0;
             read_length += len;
@@ -74,9 +200,19 @@ impl Provider for ProviderAdaptor {
     }
 
     fn write_storage(&mut self, offset: u32, data: &[u8]) -> Result<()> {
-        let length = data.len() as u32;
+        let length = u32::try_from(data.len()).map_err(|_| Error::StorageOutOfBounds {
+            offset,
+            length: u32::MAX,
+        })?;
+        let end = self.check_bounds(offset, length)?;
+        if length == 0 {
+            return Ok(());
+        }
+
         let first_page = offset / self.page_size;
-        let last_page = (offset + length) / self.page_size;
+        let last_page = (end - 1) / self.page_size;
+        self.prefetch_pages(first_page, last_page)?;
+
         let mut write_length = 0;
         let page_size = self.page_size;
         let mut page_start_offset = offset % page_size;
@@ -84,24 +220,22 @@ impl Provider for ProviderAdaptor {
         for page_no in first_page..last_page + 1 {
             let page = self.read_page(page_no)?;
 
-            let (_, right) = page.data.split_at_mut(page_start_offset as usize);
-            let mut buffer = right;
-
             let mut len = length - write_length;
             if len > page_size - page_start_offset {
                 len = page_size - page_start_offset;
-            } else {
-                let (left, _) = buffer.split_at_mut(len as usize);
-                buffer = left;
             }
 
             let d = &data[write_length as usize..(write_length + len) as usize];
-            buffer.copy_from_slice(d);
+            page.write(page_start_offset, d);
 
             page_start_offset = 0;
             write_length += len;
         }
 
+        if end > self.written_end {
+            self.written_end = end;
+        }
+
         Ok(())
     }
 }
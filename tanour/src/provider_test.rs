@@ -4,12 +4,31 @@ use crate::blockchain_api::MockBlockchainAPI;
 
 use super::*;
 
-#[test]
-fn test_read() {
+fn make_test_api(storage_size: u32) -> Box<MockBlockchainAPI> {
     let mut api = Box::new(MockBlockchainAPI::new());
     api.expect_page_size().returning(|| Ok(256));
-    api.expect_read_page().returning(|_| Ok(vec![0; 256]));
-    let mut provider = ProviderAdaptor::new(api).unwrap();
+    api.expect_storage_size()
+        .returning(move || Ok(storage_size));
+    api
+}
+
+/// Returns the given data for all the pages.
+fn expect_read_pages(api: &mut MockBlockchainAPI, data: Vec<u8>) {
+    let page = data.clone();
+    api.expect_read_page().returning(move |_| Ok(page.clone()));
+    api.expect_read_pages()
+        .returning(move |page_nos| Ok(vec![data.clone(); page_nos.len()]));
+}
+
+fn make_test_provider() -> ProviderAdaptor {
+    let mut api = make_test_api(0);
+    expect_read_pages(&mut api, vec![0; 256]);
+    ProviderAdaptor::new(api, 1024).unwrap()
+}
+
+#[test]
+fn test_read() {
+    let mut provider = make_test_provider();
 
     let data = provider.read_storage(3, 12).expect("Reading failed");
     assert_eq!(data, vec![0; 12]);
@@ -17,10 +36,7 @@ fn test_read() {
 
 #[test]
 fn test_write() {
-    let mut api = Box::new(MockBlockchainAPI::new());
-    api.expect_page_size().returning(|| Ok(256));
-    api.expect_read_page().returning(|_| Ok(vec![0; 256]));
-    let mut provider = ProviderAdaptor::new(api).unwrap();
+    let mut provider = make_test_provider();
 
     let data = vec![1, 2, 3];
     provider.write_storage(3, &data).expect("Writing failed");
@@ -28,3 +44,157 @@ fn test_write() {
     let expected = provider.read_storage(3, 3).expect("Reading failed");
     assert_eq!(data, expected);
 }
+
+#[test]
+fn test_updated_pages() {
+    let mut provider = make_test_provider();
+
+    provider.read_storage(0, 12).expect("Reading failed");
+    provider.write_storage(510, &[1, 2, 3]).expect("Writing failed");
+
+    let pages = provider.updated_pages();
+    assert_eq!(pages.len(), 2);
+    assert_eq!(pages[0].0, 1);
+    assert_eq!(pages[0].1[254..], [1, 2]);
+    assert_eq!(pages[1].0, 2);
+    assert_eq!(pages[1].1[0], 3);
+}
+
+#[test]
+fn test_out_of_bounds() {
+    let mut provider = make_test_provider();
+
+    assert!(provider.read_storage(1020, 4).is_ok());
+    assert!(matches!(
+        provider.read_storage(1020, 5),
+        Err(Error::StorageOutOfBounds {
+            offset: 1020,
+            length: 5
+        })
+    ));
+    assert!(matches!(
+        provider.read_storage(u32::MAX, 2),
+        Err(Error::StorageOutOfBounds { .. })
+    ));
+    assert!(matches!(
+        provider.write_storage(1023, &[1, 2]),
+        Err(Error::StorageOutOfBounds { .. })
+    ));
+}
+
+#[test]
+fn test_storage_growth() {
+    let mut api = make_test_api(300);
+    expect_read_pages(&mut api, vec![0; 256]);
+    let mut provider = ProviderAdaptor::new(api, 1024).unwrap();
+
+    provider.write_storage(10, &[1, 2, 3]).expect("Writing failed");
+    assert_eq!(provider.storage_growth(), 0);
+
+    provider.write_storage(400, &[1, 2, 3]).expect("Writing failed");
+    assert_eq!(provider.storage_growth(), 103);
+}
+
+#[test]
+fn test_prefetch() {
+    let mut api = make_test_api(0);
+    api.expect_read_page().times(0);
+    api.expect_read_pages()
+        .withf(|page_nos| page_nos == [0, 1, 2])
+        .times(1)
+        .returning(|page_nos| Ok(vec![vec![0; 256]; page_nos.len()]));
+    let mut provider = ProviderAdaptor::new(api, 1024).unwrap();
+
+    let data = provider.read_storage(100, 500).expect("Reading failed");
+    assert_eq!(data, vec![0; 500]);
+
+    // Pages are cached now
+    let data = provider.read_storage(0, 768).expect("Reading failed");
+    assert_eq!(data, vec![0; 768]);
+}
+
+#[test]
+fn test_prefetch_missing_pages() {
+    let mut api = make_test_api(0);
+    api.expect_read_page()
+        .with(eq(1))
+        .times(1)
+        .returning(|_| Ok(vec![0; 256]));
+    api.expect_read_pages()
+        .withf(|page_nos| page_nos == [0, 2])
+        .times(1)
+        .returning(|page_nos| Ok(vec![vec![0; 256]; page_nos.len()]));
+    let mut provider = ProviderAdaptor::new(api, 1024).unwrap();
+
+    provider.read_storage(300, 10).expect("Reading failed");
+    provider.read_storage(0, 768).expect("Reading failed");
+}
+
+#[test]
+fn test_commit() {
+    let mut api = make_test_api(0);
+    expect_read_pages(&mut api, vec![0; 256]);
+    api.expect_write_page().times(0);
+    api.expect_write_pages()
+        .withf(|pages| pages.len() == 2 && pages[0].0 == 1 && pages[1].0 == 2)
+        .times(1)
+        .returning(|_| Ok(()));
+    let mut provider = ProviderAdaptor::new(api, 1024).unwrap();
+
+    provider.read_storage(0, 12).expect("Reading failed");
+    provider.write_storage(510, &[1, 2, 3]).expect("Writing failed");
+    provider.commit().expect("Committing failed");
+
+    // Nothing to commit
+    assert!(provider.updated_pages().is_empty());
+    provider.commit().expect("Committing failed");
+}
+
+#[test]
+fn test_zero_pages() {
+    let mut api = make_test_api(0);
+    // Zero pages are returned without data
+    expect_read_pages(&mut api, Vec::new());
+    api.expect_write_pages()
+        .withf(|pages| pages.len() == 1 && pages[0].0 == 1 && pages[0].1.len() == 256)
+        .times(1)
+        .returning(|_| Ok(()));
+    let mut provider = ProviderAdaptor::new(api, 1024).unwrap();
+
+    let data = provider.read_storage(100, 500).expect("Reading failed");
+    assert_eq!(data, vec![0; 500]);
+
+    provider.write_storage(0, &[0, 0, 0]).expect("Writing failed");
+    provider.write_storage(300, &[1, 2, 3]).expect("Writing failed");
+
+    // Page 0 remains zero and it is not written back
+    provider.commit().expect("Committing failed");
+}
+
+#[test]
+fn test_storage_diffs() {
+    let mut api = make_test_api(0);
+    expect_read_pages(&mut api, vec![1; 256]);
+    let mut provider = ProviderAdaptor::new(api, 1024).unwrap();
+
+    provider.write_storage(10, &[1, 2, 3]).expect("Writing failed");
+    provider.write_storage(254, &[5, 5, 5, 5]).expect("Writing failed");
+
+    let diffs = provider.storage_diffs();
+    assert_eq!(
+        diffs,
+        vec![
+            StorageDiff {
+                offset: 11,
+                old_data: vec![1, 1],
+                new_data: vec![2, 3],
+            },
+            // The range crosses the page boundary
+            StorageDiff {
+                offset: 254,
+                old_data: vec![1, 1, 1, 1],
+                new_data: vec![5, 5, 5, 5],
+            },
+        ]
+    );
+}
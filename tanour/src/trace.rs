@@ -0,0 +1,492 @@
+//! Recording the executions, to debug the contracts and to replay the executions offline.
+//!
+//! The trace keeps the entry calls, the host function calls, the storage reads and writes,
+//! the gas checkpoints and the responses of the blockchain API, in the order they happen.
+//! The trace file is a JSON document and the bytes are written in hex.
+//!
+//! The header of the trace keeps the contract address, the code hash and the params of the execution.
+//!
+//! A recorded execution can be replayed offline by `replay`, the storage and the accounts
+//! are read from the trace and the results, the gas usage, the host calls and the storage writes
+//! are verified.
+
+use crate::blockchain_api::BlockchainAPI;
+use crate::chain::ReplayChain;
+use crate::contract::{Contract, Params};
+use crate::error::{Error, Result};
+use crate::gas::GasSchedule;
+use crate::Address;
+use blake2::digest::{Update, VariableOutput};
+use blake2::VarBlake2b;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        hex::decode(s).map_err(serde::de::Error::custom)
+    }
+}
+
+mod hex_list {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(list: &[Vec<u8>], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(list.iter().map(hex::encode))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<Vec<u8>>, D::Error> {
+        let list = Vec::<String>::deserialize(deserializer)?;
+        list.iter()
+            .map(|s| hex::decode(s).map_err(serde::de::Error::custom))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum TraceEvent {
+    /// An entry function of the contract is called with the encoded argument.
+    Call {
+        function: String,
+        #[serde(with = "hex_bytes")]
+        arg: Vec<u8>,
+    },
+    /// The entry function is returned. The data is empty if it is failed.
+    Return {
+        function: String,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+        error: Option<String>,
+        consumed_points: u64,
+    },
+    /// A host function is called by the contract.
+    HostCall {
+        function: String,
+        args: Vec<u32>,
+        result: Option<u32>,
+        error: Option<String>,
+    },
+    StorageRead {
+        offset: u32,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+    },
+    StorageWrite {
+        offset: u32,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+    },
+    /// The remaining points after calling a Wasm function.
+    Gas {
+        function: String,
+        remaining_points: u64,
+    },
+    PageSize {
+        size: u32,
+    },
+    StorageSize {
+        size: u32,
+    },
+    ReadPage {
+        page_no: u32,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+    },
+    ReadPages {
+        page_nos: Vec<u32>,
+        #[serde(with = "hex_list")]
+        pages: Vec<Vec<u8>>,
+    },
+    WritePage {
+        page_no: u32,
+        #[serde(with = "hex_bytes")]
+        data: Vec<u8>,
+    },
+    WritePages {
+        page_nos: Vec<u32>,
+        #[serde(with = "hex_list")]
+        pages: Vec<Vec<u8>>,
+    },
+    Exist {
+        #[serde(with = "hex_bytes")]
+        address: Vec<u8>,
+        exist: bool,
+    },
+    BlockNumber {
+        number: u32,
+    },
+}
+
+/// The contract and the params of the recorded execution.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceHeader {
+    #[serde(with = "hex_bytes")]
+    pub address: Vec<u8>,
+    /// The Blake2b-256 hash of the contract code
+    #[serde(with = "hex_bytes")]
+    pub code_hash: Vec<u8>,
+    pub memory_limit_page: u32,
+    pub metering_limit: u64,
+    pub stack_height_limit: u32,
+    pub storage_limit: u32,
+    pub gas_schedule: GasSchedule,
+}
+
+fn code_hash(code: &[u8]) -> Vec<u8> {
+    let mut hasher = VarBlake2b::new(32).unwrap();
+    hasher.update(code);
+    hasher.finalize_boxed().to_vec()
+}
+
+impl TraceHeader {
+    pub fn new(address: &Address, code: &[u8], params: &Params) -> Self {
+        TraceHeader {
+            address: address.to_vec(),
+            code_hash: code_hash(code),
+            memory_limit_page: params.memory_limit_page,
+            metering_limit: params.metering_limit,
+            stack_height_limit: params.stack_height_limit,
+            storage_limit: params.storage_limit,
+            gas_schedule: params.gas_schedule,
+        }
+    }
+
+    /// Checks that the trace is recorded for the contract.
+    fn check(&self, address: &Address, code: &[u8]) -> Result<()> {
+        if self.address != address {
+            return Err(Error::ReplayError {
+                msg: format!(
+                    "the trace is recorded for the contract {}",
+                    hex::encode(&self.address)
+                ),
+            });
+        }
+        if self.code_hash != code_hash(code) {
+            return Err(Error::ReplayError {
+                msg: format!(
+                    "the trace is recorded for the code with hash {}",
+                    hex::encode(&self.code_hash)
+                ),
+            });
+        }
+        Ok(())
+    }
+
+    /// Sets the params to the recorded params.
+    fn apply(&self, params: &mut Params) {
+        params.memory_limit_page = self.memory_limit_page;
+        params.metering_limit = self.metering_limit;
+        params.stack_height_limit = self.stack_height_limit;
+        params.storage_limit = self.storage_limit;
+        params.gas_schedule = self.gas_schedule;
+    }
+}
+
+/// The recorded events of an execution.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trace {
+    /// It is not set if the events are not recorded by a contract.
+    #[serde(default)]
+    pub header: Option<TraceHeader>,
+    pub events: Vec<TraceEvent>,
+}
+
+#[cfg(feature = "codec")]
+fn codec_error<E: std::fmt::Display>(original: E) -> Error {
+    Error::CodecError {
+        msg: format!("{original}"),
+    }
+}
+
+#[cfg(feature = "codec")]
+impl Trace {
+    /// Writes the trace into the file as JSON.
+    pub fn write_file(&self, path: &std::path::Path) -> Result<()> {
+        let json = serde_json::to_vec_pretty(self).map_err(codec_error)?;
+        std::fs::write(path, json)?;
+        Ok(())
+    }
+
+    /// Reads the trace from the JSON file.
+    pub fn read_file(path: &std::path::Path) -> Result<Self> {
+        let json = std::fs::read(path)?;
+        serde_json::from_slice(&json).map_err(codec_error)
+    }
+}
+
+impl Trace {
+    /// Returns the entry function calls with their arguments, in the order they are called.
+    pub fn calls(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.events.iter().filter_map(|event| match event {
+            TraceEvent::Call { function, arg } => Some((function.as_str(), arg.as_slice())),
+            _ => None,
+        })
+    }
+
+    /// Returns the events that the replayed execution should reproduce.
+    fn verified_events(&self) -> impl Iterator<Item = &TraceEvent> {
+        self.events.iter().filter(|event| {
+            matches!(
+                event,
+                TraceEvent::Return { .. }
+                    | TraceEvent::HostCall { .. }
+                    | TraceEvent::WritePage { .. }
+                    | TraceEvent::WritePages { .. }
+            )
+        })
+    }
+
+    /// Verifies that the replayed trace has the same header, and the entry functions
+    /// in the replayed trace return the same data and errors, consume the same points,
+    /// make the same host calls and write the same pages as this trace.
+    pub fn verify(&self, replayed: &Trace) -> Result<()> {
+        if let (Some(recorded), Some(replayed)) = (&self.header, &replayed.header) {
+            if recorded != replayed {
+                return Err(Error::ReplayError {
+                    msg: format!("header mismatched: recorded {recorded:?}, replayed {replayed:?}"),
+                });
+            }
+        }
+
+        let recorded: Vec<_> = self.verified_events().collect();
+        let replayed: Vec<_> = replayed.verified_events().collect();
+        for (index, (recorded, replayed)) in recorded.iter().zip(&replayed).enumerate() {
+            if recorded != replayed {
+                return Err(Error::ReplayError {
+                    msg: format!(
+                        "event {index} mismatched: recorded {recorded:?}, replayed {replayed:?}"
+                    ),
+                });
+            }
+        }
+        if recorded.len() != replayed.len() {
+            return Err(Error::ReplayError {
+                msg: format!(
+                    "{} events are recorded, but {} events are replayed",
+                    recorded.len(),
+                    replayed.len()
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Re-runs the recorded execution offline and verifies the result and the gas usage.
+/// If the trace has a header, the address and the code should match it and the recorded params
+/// are used instead of the given limits and gas schedule. Otherwise the params should be the same
+/// as the recorded execution, or the gas usage may differ.
+/// Returns the trace of the replayed execution.
+pub fn replay(address: &Address, code: &[u8], trace: &Trace, mut params: Params) -> Result<Trace> {
+    if let Some(header) = &trace.header {
+        header.check(address, code)?;
+        header.apply(&mut params);
+    }
+
+    let recorder = TraceRecorder::new();
+    params.trace = Some(recorder.clone());
+    let chain = ReplayChain::new(trace);
+    let mut contract = Contract::new(Box::new(chain.clone()), address, code, params)?;
+
+    for (function, arg) in trace.calls() {
+        // The errors are recorded in the trace and they are verified as well.
+        let _ = match function {
+            "instantiate" => contract.call_instantiate(arg),
+            "process" => contract.call_process(arg),
+            "query" => contract.call_query(arg),
+            _ => {
+                return Err(Error::ReplayError {
+                    msg: format!("unknown entry function: {function}"),
+                })
+            }
+        };
+    }
+
+    chain.check()?;
+    let replayed = recorder.trace();
+    trace.verify(&replayed)?;
+    Ok(replayed)
+}
+
+/// Records the trace events. The clones share the same header and events.
+#[derive(Debug, Clone, Default)]
+pub struct TraceRecorder {
+    header: Arc<Mutex<Option<TraceHeader>>>,
+    events: Arc<Mutex<Vec<TraceEvent>>>,
+}
+
+impl TraceRecorder {
+    pub fn new() -> Self {
+        TraceRecorder::default()
+    }
+
+    /// Sets the header of the trace, the header of the first contract is kept.
+    pub fn set_header(&self, header: TraceHeader) {
+        if let Ok(mut current) = self.header.lock() {
+            current.get_or_insert(header);
+        }
+    }
+
+    pub fn record(&self, event: TraceEvent) {
+        if let Ok(mut events) = self.events.lock() {
+            events.push(event);
+        }
+    }
+
+    /// Returns the events that are recorded so far.
+    pub fn trace(&self) -> Trace {
+        let events = match self.events.lock() {
+            Ok(events) => events.clone(),
+            Err(_) => Vec::new(),
+        };
+        let header = self.header.lock().ok().and_then(|header| header.clone());
+        Trace { header, events }
+    }
+}
+
+/// Records the responses of the blockchain API.
+pub(crate) struct RecordingAPI {
+    api: Box<dyn BlockchainAPI>,
+    recorder: TraceRecorder,
+}
+
+impl RecordingAPI {
+    pub fn new(api: Box<dyn BlockchainAPI>, recorder: TraceRecorder) -> Self {
+        RecordingAPI { api, recorder }
+    }
+}
+
+impl BlockchainAPI for RecordingAPI {
+    fn page_size(&self) -> Result<u32> {
+        let size = self.api.page_size()?;
+        self.recorder.record(TraceEvent::PageSize { size });
+        Ok(size)
+    }
+
+    fn storage_size(&self) -> Result<u32> {
+        let size = self.api.storage_size()?;
+        self.recorder.record(TraceEvent::StorageSize { size });
+        Ok(size)
+    }
+
+    fn read_page(&self, page_no: u32) -> Result<Vec<u8>> {
+        let data = self.api.read_page(page_no)?;
+        self.recorder.record(TraceEvent::ReadPage {
+            page_no,
+            data: data.clone(),
+        });
+        Ok(data)
+    }
+
+    fn read_pages(&self, page_nos: &[u32]) -> Result<Vec<Vec<u8>>> {
+        let pages = self.api.read_pages(page_nos)?;
+        self.recorder.record(TraceEvent::ReadPages {
+            page_nos: page_nos.to_vec(),
+            pages: pages.clone(),
+        });
+        Ok(pages)
+    }
+
+    fn write_page(&self, page_no: u32, data: &[u8]) -> Result<()> {
+        self.api.write_page(page_no, data)?;
+        self.recorder.record(TraceEvent::WritePage {
+            page_no,
+            data: data.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn write_pages(&self, pages: &[(u32, Vec<u8>)]) -> Result<()> {
+        self.api.write_pages(pages)?;
+        self.recorder.record(TraceEvent::WritePages {
+            page_nos: pages.iter().map(|(page_no, _)| *page_no).collect(),
+            pages: pages.iter().map(|(_, data)| data.clone()).collect(),
+        });
+        Ok(())
+    }
+
+    fn exist(&self, address: &Address) -> Result<bool> {
+        let exist = self.api.exist(address)?;
+        self.recorder.record(TraceEvent::Exist {
+            address: address.to_vec(),
+            exist,
+        });
+        Ok(exist)
+    }
+
+    fn current_block_number(&self) -> u32 {
+        let number = self.api.current_block_number();
+        self.recorder.record(TraceEvent::BlockNumber { number });
+        number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serialize() {
+        let recorder = TraceRecorder::new();
+        recorder.record(TraceEvent::Call {
+            function: "process".to_string(),
+            arg: vec![1, 2],
+        });
+        recorder.clone().record(TraceEvent::ReadPages {
+            page_nos: vec![0, 1],
+            pages: vec![vec![0xff], Vec::new()],
+        });
+
+        let trace = recorder.trace();
+        assert_eq!(trace.events.len(), 2);
+
+        let json = serde_json::to_string(&trace).unwrap();
+        assert!(json.contains(r#"{"event":"call","function":"process","arg":"0102"}"#));
+        assert_eq!(serde_json::from_str::<Trace>(&json).unwrap(), trace);
+    }
+
+    #[test]
+    fn test_verify() {
+        let recorded = Trace {
+            header: None,
+            events: vec![
+                TraceEvent::WritePage {
+                    page_no: 1,
+                    data: vec![1, 2],
+                },
+                TraceEvent::ReadPage {
+                    page_no: 2,
+                    data: vec![3],
+                },
+            ],
+        };
+        // The responses of the blockchain API are not verified
+        let mut replayed = recorded.clone();
+        replayed.events.pop();
+        assert!(recorded.verify(&replayed).is_ok());
+
+        replayed.events[0] = TraceEvent::WritePage {
+            page_no: 1,
+            data: vec![1, 3],
+        };
+        assert!(matches!(
+            recorded.verify(&replayed),
+            Err(Error::ReplayError { .. })
+        ));
+
+        replayed.events.clear();
+        assert!(matches!(
+            recorded.verify(&replayed),
+            Err(Error::ReplayError { .. })
+        ));
+    }
+}
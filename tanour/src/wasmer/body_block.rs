@@ -0,0 +1,122 @@
+use wasmer::wasmparser::{Operator, Type as WpType, TypeOrFuncType as WpTypeOrFuncType};
+use wasmer::{FunctionType, LocalFunctionIndex, MiddlewareReaderState, Type};
+use wasmer_types::ModuleInfo;
+
+/// Returns the block types to wrap the body of the local functions in a block.
+///
+/// A branch to the function label leaves the function, like `return`.
+/// When the body is wrapped in a block with the function results, these branches
+/// leave the block instead, so the code injected after the block runs on all the paths
+/// that leave the function, except `return`.
+pub fn body_block_types(module_info: &mut ModuleInfo) -> Vec<WpTypeOrFuncType> {
+    let num_local_functions = module_info.functions.len() - module_info.num_imported_functions;
+    let mut block_types = Vec::with_capacity(num_local_functions);
+    for index in 0..num_local_functions {
+        let function_index = module_info.func_index(LocalFunctionIndex::from_u32(index as u32));
+        let signature_index = module_info.functions[function_index];
+        let results = module_info.signatures[signature_index].results().to_vec();
+
+        let block_type = match results.as_slice() {
+            [] => WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+            [result] => WpTypeOrFuncType::Type(wp_type(*result)),
+            _ => {
+                // Multiple results need a signature without params
+                let signature = FunctionType::new(vec![], results);
+                let index = module_info.signatures.push(signature);
+                WpTypeOrFuncType::FuncType(index.as_u32())
+            }
+        };
+        block_types.push(block_type);
+    }
+    block_types
+}
+
+/// The state of the block that wraps a function body.
+#[derive(Debug)]
+pub struct BodyBlock {
+    /// The block type of the function body.
+    block_type: WpTypeOrFuncType,
+    /// True if the function prologue is injected.
+    entered: bool,
+    /// The depth of nested blocks inside the function body.
+    depth: u32,
+}
+
+impl BodyBlock {
+    pub fn new(block_type: WpTypeOrFuncType) -> Self {
+        Self {
+            block_type,
+            entered: false,
+            depth: 0,
+        }
+    }
+}
+
+/// A function middleware that wraps the function body in a block,
+/// to inject the code when the function is entered and when it is left.
+pub trait BodyBlockMiddleware {
+    fn body_block(&mut self) -> &mut BodyBlock;
+
+    /// Injects the code before the body block.
+    fn enter(&self, state: &mut MiddlewareReaderState<'_>);
+
+    /// Injects the code after the body block.
+    fn leave(&self, state: &mut MiddlewareReaderState<'_>);
+
+    /// Injects the code for `return`, inside `depth` nested blocks of the body block.
+    /// The function is left before returning by default.
+    fn feed_return(&self, depth: u32, state: &mut MiddlewareReaderState<'_>) {
+        let _ = depth;
+        self.leave(state);
+        state.push_operator(Operator::Return);
+    }
+
+    /// Injects the body block and the code to enter and leave the function around the operator.
+    /// Returns the operator if it is not fed yet.
+    fn feed_body<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Option<Operator<'a>> {
+        if !self.body_block().entered {
+            self.enter(state);
+            let ty = self.body_block().block_type;
+            state.push_operator(Operator::Block { ty });
+            self.body_block().entered = true;
+        }
+
+        match operator {
+            Operator::Block { .. } | Operator::Loop { .. } | Operator::If { .. } => {
+                self.body_block().depth += 1;
+            }
+            Operator::End => {
+                if self.body_block().depth == 0 {
+                    // The end of the function body, closing the body block first
+                    state.push_operator(Operator::End);
+                    self.leave(state);
+                } else {
+                    self.body_block().depth -= 1;
+                }
+            }
+            Operator::Return => {
+                let depth = self.body_block().depth;
+                self.feed_return(depth, state);
+                return None;
+            }
+            _ => {}
+        }
+        Some(operator)
+    }
+}
+
+fn wp_type(ty: Type) -> WpType {
+    match ty {
+        Type::I32 => WpType::I32,
+        Type::I64 => WpType::I64,
+        Type::F32 => WpType::F32,
+        Type::F64 => WpType::F64,
+        Type::V128 => WpType::V128,
+        Type::ExternRef => WpType::ExternRef,
+        Type::FuncRef => WpType::FuncRef,
+    }
+}
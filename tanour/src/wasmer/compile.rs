@@ -1,27 +1,83 @@
 use super::limiting_tunables::LimitingTunables;
+use super::profiling::Profiling;
+use super::stack_limit::StackLimit;
+use crate::cache::{ModuleCache, ModuleKey};
+use crate::contract::CompileInfo;
 use crate::error::{Error, Result};
-use log::debug;
+use crate::gas::GasSchedule;
+use blake2::digest::{Update, VariableOutput};
+use blake2::VarBlake2b;
 use std::sync::Arc;
+use std::time::Instant;
+use tracing::debug;
 use wasmer::{
     wasmparser::Operator, BaseTunables, CompilerConfig, EngineBuilder, Module, Pages, Singlepass,
     Store, Target,
 };
 use wasmer_middlewares::Metering;
 
+/// Returns the cache key of the module.
+/// The limits, the gas schedule and profiling are part of the key, since they are compiled into the module.
+/// The versions of Wasmer and Tanour are part of the key, since the serialized modules
+/// are only valid for the engine and the middlewares that compiled them.
+fn module_key(
+    code: &[u8],
+    memory_limit_page: u32,
+    metering_limit: u64,
+    stack_height_limit: u32,
+    gas_schedule: &GasSchedule,
+    profiling: bool,
+) -> ModuleKey {
+    let mut hasher = VarBlake2b::new(32).unwrap();
+    hasher.update(wasmer::VERSION);
+    hasher.update(env!("CARGO_PKG_VERSION"));
+    hasher.update(code);
+    hasher.update(memory_limit_page.to_le_bytes());
+    hasher.update(metering_limit.to_le_bytes());
+    hasher.update(stack_height_limit.to_le_bytes());
+    hasher.update(gas_schedule.default_cost.to_le_bytes());
+    hasher.update(gas_schedule.call_cost.to_le_bytes());
+    hasher.update(gas_schedule.memory_grow_cost.to_le_bytes());
+    hasher.update([profiling as u8]);
+
+    let mut key = ModuleKey::default();
+    hasher.finalize_variable(|res| key.copy_from_slice(res));
+    key
+}
+
 /// Compiles a given Wasm bytecode into a module.
 /// The given memory limit (in bytes) is used when memories are created.
+/// The given stack height limit is the maximum values that the function frames of the contract can keep.
+/// If profiling is set, the module measures the metering points that each function consumes.
+/// If the module cache is given, the compiled module is looked up in the cache first.
+/// It also returns how long compiling, or loading from the cache, took.
+#[tracing::instrument(skip_all, fields(code_size = code.len()))]
 pub fn compile(
     code: &[u8],
     memory_limit_page: u32,
     metering_limit: u64,
-) -> Result<(Module, Store)> {
-    debug!("compiling the code");
+    stack_height_limit: u32,
+    gas_schedule: &GasSchedule,
+    profiling: bool,
+    module_cache: Option<&ModuleCache>,
+) -> Result<(Module, Store, CompileInfo)> {
+    let started = Instant::now();
     let mut config = Singlepass::default();
 
-    let cost_function = |_: &Operator| -> u64 { 1 };
+    let gas_schedule = *gas_schedule;
+    let cost_function = move |operator: &Operator| -> u64 { gas_schedule.cost(operator) };
     let metering = Arc::new(Metering::new(metering_limit, cost_function));
     config.push_middleware(metering);
 
+    // Stack limit is pushed after metering, so the injected operators are not metered.
+    let stack_limit = Arc::new(StackLimit::new(stack_height_limit, code));
+    config.push_middleware(stack_limit);
+
+    // Profiling reads the remaining points of the metering, so it is pushed after metering.
+    if profiling {
+        config.push_middleware(Arc::new(Profiling::new(code)));
+    }
+
     let engine = EngineBuilder::new(config);
 
     let base = BaseTunables::for_target(&Target::default());
@@ -29,9 +85,46 @@ pub fn compile(
     let store = Store::new_with_tunables(engine, tunables);
     //let store = Store::default();
 
+    let key = module_key(
+        code,
+        memory_limit_page,
+        metering_limit,
+        stack_height_limit,
+        &gas_schedule,
+        profiling,
+    );
+    if let Some(data) = module_cache.and_then(|cache| cache.get(&key)) {
+        // Safety: the serialized module is created by `Module::serialize` below, with the same
+        // Wasmer version and middlewares, since they are part of the key. The modules on disk
+        // are verified by their checksum, and the cache directory is trusted like the binary.
+        match unsafe { Module::deserialize(&store, data) } {
+            Ok(module) => {
+                debug!("the module is loaded from the cache");
+                let info = CompileInfo {
+                    duration: started.elapsed(),
+                    cache_hit: true,
+                };
+                return Ok((module, store, info));
+            }
+            Err(err) => debug!("unable to deserialize the cached module: {err}"),
+        }
+    }
+
+    debug!("compiling the code");
     let module = Module::new(&store, code).map_err(|original| Error::CompileError {
         msg: format!("{original}"),
     })?;
 
-    Ok((module, store))
+    if let Some(cache) = module_cache {
+        match module.serialize() {
+            Ok(data) => cache.put(&key, data.to_vec()),
+            Err(err) => debug!("unable to serialize the module: {err}"),
+        }
+    }
+
+    let info = CompileInfo {
+        duration: started.elapsed(),
+        cache_hit: false,
+    };
+    Ok((module, store, info))
 }
@@ -1,17 +1,23 @@
 use super::compile;
 use super::memory;
 use super::native::*;
-use crate::error::{Error, Result};
+use super::profiling::{get_profile, reset_profile};
+use super::stack_limit::{get_stack_height, reset_stack_height};
+use crate::contract::{CompileInfo, Params};
+use crate::error::{Error, Frame, Result, TrapCode};
 use crate::executor;
 use crate::memory::Pointer;
+use crate::profile::Profile;
 use crate::provider::Provider;
+use crate::trace::{TraceEvent, TraceRecorder};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex;
 use wasmer::AsStoreRef;
 use wasmer::Memory;
 use wasmer::Store;
-use wasmer::{imports, AsStoreMut, Function, FunctionEnv, Value};
-use wasmer_middlewares::metering::{get_remaining_points, MeteringPoints};
+use wasmer::{imports, AsStoreMut, Function, FunctionEnv, RuntimeError, Value};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
 
 #[derive(Debug, Clone)]
 pub struct ResultData {
@@ -23,6 +29,24 @@ pub struct ResultData {
 pub(super) struct Env {
     pub provider: Arc<Mutex<dyn Provider>>,
     pub memory: Option<Memory>,
+    pub recorder: Option<TraceRecorder>,
+}
+
+impl Env {
+    pub fn record(&self, event: TraceEvent) {
+        if let Some(recorder) = &self.recorder {
+            recorder.record(event);
+        }
+    }
+
+    pub fn record_host_call(&self, function: &str, args: &[u32], result: &Result<u32>) {
+        self.record(TraceEvent::HostCall {
+            function: function.to_string(),
+            args: args.to_vec(),
+            result: result.as_ref().ok().copied(),
+            error: result.as_ref().err().map(|err| format!("{err}")),
+        });
+    }
 }
 
 pub struct WasmerExecutor {
@@ -31,26 +55,46 @@ pub struct WasmerExecutor {
 
     // The limit for metering middleware
     metering_limit: u64,
+
+    // The points that the execution is given, up to the metering limit
+    gas_limit: AtomicU64,
+
+    // The limit for stack limit middleware
+    stack_height_limit: u32,
+
+    compile_info: CompileInfo,
+
+    // Records the execution trace, if it is set
+    recorder: Option<TraceRecorder>,
 }
 
 impl WasmerExecutor {
     /// creates the new instance of WASMER executor
     /// `code` should be the wat byte codes
-    /// `memory_limit_page` is the maximum a linear memory is allowed to be (in Wasm pages, 64 KiB each).
-    /// `metering_limit` is the maximum operator that can be  executed in total.
-    pub fn new(
-        code: &[u8],
-        memory_limit_page: u32,
-        metering_limit: u64,
-        provider: Arc<Mutex<dyn Provider>>,
-    ) -> Result<Self> {
-        let (module, store) = compile::compile(code, memory_limit_page, metering_limit)?;
+    /// `params.memory_limit_page` is the maximum a linear memory is allowed to be (in Wasm pages, 64 KiB each).
+    /// `params.metering_limit` is the maximum operator that can be  executed in total.
+    /// `params.stack_height_limit` is the maximum stack height, in the values that the function frames keep.
+    /// `params.gas_schedule` defines the metering points of the operators.
+    /// `params.module_cache` keeps the compiled modules, if it is given.
+    /// `params.trace` records the host function calls, the storage reads and writes and the gas checkpoints.
+    /// `params.profiling` measures the metering points that each function consumes.
+    pub fn new(code: &[u8], params: &Params, provider: Arc<Mutex<dyn Provider>>) -> Result<Self> {
+        let (module, store, compile_info) = compile::compile(
+            code,
+            params.memory_limit_page,
+            params.metering_limit,
+            params.stack_height_limit,
+            &params.gas_schedule,
+            params.profiling,
+            params.module_cache.as_deref(),
+        )?;
         let store_lock = Arc::new(Mutex::new(store));
         let mut store_guard = store_lock.lock().unwrap();
 
         let env = Env {
             provider,
             memory: None,
+            recorder: params.trace.clone(),
         };
         let fun_env = FunctionEnv::new(&mut store_guard.as_store_mut(), env);
 
@@ -63,11 +107,13 @@ impl WasmerExecutor {
             }
         };
 
-        let instance =
-            wasmer::Instance::new(&mut store_guard.as_store_mut(), &module, &import_object)
-                .map_err(|original| Error::InstantiationError {
+        let instance = tracing::debug_span!("instantiate").in_scope(|| {
+            wasmer::Instance::new(&mut store_guard.as_store_mut(), &module, &import_object).map_err(
+                |original| Error::InstantiationError {
                     msg: format!("{original}"),
-                })?;
+                },
+            )
+        })?;
 
         fun_env.as_mut(&mut store_guard.as_store_mut()).memory = Some(
             instance
@@ -82,7 +128,11 @@ impl WasmerExecutor {
         Ok(WasmerExecutor {
             instance,
             store_lock: store_lock.clone(),
-            metering_limit,
+            metering_limit: params.metering_limit,
+            gas_limit: AtomicU64::new(params.metering_limit),
+            stack_height_limit: params.stack_height_limit,
+            compile_info,
+            recorder: params.trace.clone(),
         })
     }
 
@@ -91,8 +141,8 @@ impl WasmerExecutor {
             .instance
             .exports
             .get_function(name)
-            .map_err(|original| Error::RuntimeError {
-                msg: format!("{original}"),
+            .map_err(|_| Error::MissingExport {
+                name: name.to_string(),
             })?;
 
         let mut store_guard = self
@@ -102,30 +152,90 @@ impl WasmerExecutor {
                 msg: format!("{original}"),
             })?;
 
-        func.call(&mut store_guard.as_store_mut(), vals)
-            .map_err(|original| Error::RuntimeError {
-                msg: format!("{original}"),
+        reset_stack_height(&mut store_guard.as_store_mut(), &self.instance);
+        reset_profile(&mut store_guard.as_store_mut(), &self.instance);
+        let result = func.call(&mut store_guard.as_store_mut(), vals);
+
+        if let Some(recorder) = &self.recorder {
+            let remaining_points =
+                match get_remaining_points(&mut store_guard.as_store_mut(), &self.instance) {
+                    MeteringPoints::Exhausted => 0,
+                    MeteringPoints::Remaining(points) => points,
+                };
+            recorder.record(TraceEvent::Gas {
+                function: name.to_string(),
+                remaining_points,
+            });
+        }
+
+        result.map_err(|original| self.runtime_error(&mut store_guard.as_store_mut(), original))
+    }
+
+    /// Converts the Wasmer runtime error into a typed error.
+    fn runtime_error(&self, store: &mut impl AsStoreMut, original: RuntimeError) -> Error {
+        // The stack limit middleware traps when the stack height exceeds the limit.
+        if get_stack_height(store, &self.instance) > self.stack_height_limit as u64 {
+            return Error::StackOverflow {
+                limit: self.stack_height_limit,
+            };
+        }
+
+        // The metering middleware traps when all the points are consumed.
+        if let MeteringPoints::Exhausted = get_remaining_points(store, &self.instance) {
+            return Error::OutOfGas {
+                limit: self.gas_limit.load(Ordering::Relaxed),
+            };
+        }
+
+        // Errors returned by the host functions.
+        let original = match original.downcast::<Error>() {
+            Ok(err) => return err,
+            Err(original) => original,
+        };
+
+        let msg = original.message();
+        let frames = original
+            .trace()
+            .iter()
+            .map(|frame| Frame {
+                func_index: frame.func_index(),
+                func_name: frame.function_name().map(str::to_string),
+                module_offset: frame.module_offset(),
+                func_offset: frame.func_offset(),
             })
+            .collect();
+        match original.to_trap() {
+            Some(code) => Error::Trap {
+                code: trap_code(code),
+                msg,
+                frames,
+            },
+            None => Error::RuntimeError { msg },
+        }
     }
 
     fn memory(&self) -> Result<&Memory> {
         self.instance
             .exports
             .get_memory("memory")
-            .map_err(|original| Error::RuntimeError {
-                msg: format!("{original}"),
+            .map_err(|_| Error::MissingExport {
+                name: "memory".to_string(),
             })
     }
 }
 
 impl executor::Executor for WasmerExecutor {
+    fn has_function(&self, name: &str) -> bool {
+        self.instance.exports.get_function(name).is_ok()
+    }
+
     fn call_fn_0(&self, name: &str, arg: u64) -> Result<()> {
         let val = Value::I64(arg as i64);
         let result = self.call_function(name, &[val])?;
 
         match result.first() {
-            Some(_) => Err(Error::RuntimeError {
-                msg: format!("expecting no return value for {name}"),
+            Some(_) => Err(Error::InvalidReturnType {
+                name: name.to_string(),
             }),
             None => Ok(()),
         }
@@ -138,12 +248,12 @@ impl executor::Executor for WasmerExecutor {
         match result.first() {
             Some(val) => match val {
                 Value::I64(i64) => Ok(*i64 as u64),
-                _ => Err(Error::RuntimeError {
-                    msg: format!("expecting return value for {name}"),
+                _ => Err(Error::InvalidReturnType {
+                    name: name.to_string(),
                 }),
             },
-            None => Err(Error::RuntimeError {
-                msg: format!("No return value for {name}"),
+            None => Err(Error::InvalidReturnType {
+                name: name.to_string(),
             }),
         }
     }
@@ -155,12 +265,12 @@ impl executor::Executor for WasmerExecutor {
         match result.first() {
             Some(val) => match val {
                 Value::I64(i64) => Ok(*i64 as u64),
-                _ => Err(Error::RuntimeError {
-                    msg: format!("Invalid return value for {name}"),
+                _ => Err(Error::InvalidReturnType {
+                    name: name.to_string(),
                 }),
             },
-            None => Err(Error::RuntimeError {
-                msg: format!("No return value for {name}"),
+            None => Err(Error::InvalidReturnType {
+                name: name.to_string(),
             }),
         }
     }
@@ -210,8 +320,22 @@ impl executor::Executor for WasmerExecutor {
         }
     }
 
+    fn set_remaining_points(&self, points: u64) -> Result<()> {
+        let mut store_guard = self
+            .store_lock
+            .lock()
+            .map_err(|original| Error::RuntimeError {
+                msg: format!("{original}"),
+            })?;
+
+        let points = points.min(self.metering_limit);
+        set_remaining_points(&mut store_guard.as_store_mut(), &self.instance, points);
+        self.gas_limit.store(points, Ordering::Relaxed);
+        Ok(())
+    }
+
     fn consumed_points(&self) -> Result<u64> {
-        Ok(self.metering_limit - self.remaining_points()?)
+        Ok(self.gas_limit.load(Ordering::Relaxed) - self.remaining_points()?)
     }
 
     fn exhausted(&self) -> Result<bool> {
@@ -220,12 +344,45 @@ impl executor::Executor for WasmerExecutor {
             _ => Ok(false),
         }
     }
+
+    fn compile_info(&self) -> CompileInfo {
+        self.compile_info
+    }
+
+    fn profile(&self) -> Result<Option<Profile>> {
+        let mut store_guard = self
+            .store_lock
+            .lock()
+            .map_err(|original| Error::RuntimeError {
+                msg: format!("{original}"),
+            })?;
+
+        Ok(get_profile(&mut store_guard.as_store_mut(), &self.instance))
+    }
+}
+
+fn trap_code(code: wasmer::TrapCode) -> TrapCode {
+    match code {
+        wasmer::TrapCode::StackOverflow => TrapCode::StackOverflow,
+        wasmer::TrapCode::HeapAccessOutOfBounds => TrapCode::HeapAccessOutOfBounds,
+        wasmer::TrapCode::HeapMisaligned => TrapCode::HeapMisaligned,
+        wasmer::TrapCode::TableAccessOutOfBounds => TrapCode::TableAccessOutOfBounds,
+        wasmer::TrapCode::IndirectCallToNull => TrapCode::IndirectCallToNull,
+        wasmer::TrapCode::BadSignature => TrapCode::BadSignature,
+        wasmer::TrapCode::IntegerOverflow => TrapCode::IntegerOverflow,
+        wasmer::TrapCode::IntegerDivisionByZero => TrapCode::IntegerDivisionByZero,
+        wasmer::TrapCode::BadConversionToInteger => TrapCode::BadConversionToInteger,
+        wasmer::TrapCode::UnreachableCodeReached => TrapCode::UnreachableCodeReached,
+        wasmer::TrapCode::UnalignedAtomic => TrapCode::UnalignedAtomic,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::blockchain_api::MockBlockchainAPI;
+    use crate::executor::Executor;
+    use crate::gas::GasSchedule;
     use crate::provider::MockProvider;
     use wasmer::Pages;
 
@@ -234,10 +391,33 @@ mod tests {
         memory_limit_page: u32,
         metering_limit: u64,
     ) -> Result<WasmerExecutor> {
+        make_test_wasmer_with_height(wat, memory_limit_page, metering_limit, 32768)
+    }
+
+    fn make_test_wasmer_with_height(
+        wat: &str,
+        memory_limit_page: u32,
+        metering_limit: u64,
+        stack_height_limit: u32,
+    ) -> Result<WasmerExecutor> {
+        let params = Params {
+            memory_limit_page,
+            metering_limit,
+            stack_height_limit,
+            storage_limit: 1024 * 1024,
+            gas_schedule: GasSchedule::default(),
+            module_cache: None,
+            trace: None,
+            profiling: false,
+        };
+        make_test_wasmer_with_params(wat, &params)
+    }
+
+    fn make_test_wasmer_with_params(wat: &str, params: &Params) -> Result<WasmerExecutor> {
         let code = wat::parse_str(wat).unwrap();
         let _blockchain_api = MockBlockchainAPI::new();
         let provider = Arc::new(Mutex::new(MockProvider::new()));
-        WasmerExecutor::new(&code, memory_limit_page, metering_limit, provider)
+        WasmerExecutor::new(&code, params, provider)
     }
 
     #[test]
@@ -305,4 +485,552 @@ mod tests {
             .unwrap();
         assert_eq!(res.to_vec(), vec![Value::I32(3)]);
     }
+
+    const RECURSIVE_WAT: &str = r#"
+(module
+    (func $recurse (param $n i32) (result i32)
+        (if (result i32) (i32.eqz (local.get $n))
+            (then (i32.const 0))
+            (else
+                (i32.add
+                    (i32.const 1)
+                    (call $recurse (i32.sub (local.get $n) (i32.const 1)))
+                )
+            )
+        )
+    )
+    (export "recurse" (func $recurse))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+    #[test]
+    fn test_stack_height_within_limit() {
+        // Each call keeps 20 values: the frame cost, one param and three operands
+        let wasmer = make_test_wasmer_with_height(RECURSIVE_WAT, 1, 100000, 200).unwrap();
+        let res = wasmer.call_function("recurse", &[Value::I32(9)]).unwrap();
+        assert_eq!(res.to_vec(), vec![Value::I32(9)]);
+
+        // The stack height should be restored after a successful call
+        let mut guard = wasmer.store_lock.lock().unwrap();
+        assert_eq!(
+            get_stack_height(&mut guard.as_store_mut(), &wasmer.instance),
+            0
+        );
+    }
+
+    #[test]
+    fn test_stack_overflow() {
+        let wasmer = make_test_wasmer_with_height(RECURSIVE_WAT, 1, 100000, 200).unwrap();
+        let res = wasmer.call_function("recurse", &[Value::I32(10)]);
+        assert!(matches!(res, Err(Error::StackOverflow { limit: 200 })));
+
+        // The stack height should be reset for the next call
+        let res = wasmer.call_function("recurse", &[Value::I32(5)]).unwrap();
+        assert_eq!(res.to_vec(), vec![Value::I32(5)]);
+    }
+
+    #[test]
+    fn test_stack_overflow_locals() {
+        // A single frame with many locals exceeds the limit without any nested call
+        let wat = format!(
+            r#"
+(module
+    (func $locals (local {}))
+    (export "locals" (func $locals))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#,
+            "i64 ".repeat(200)
+        );
+
+        let wasmer = make_test_wasmer_with_height(&wat, 1, 100000, 200).unwrap();
+        let res = wasmer.call_function("locals", &[]);
+        assert!(matches!(res, Err(Error::StackOverflow { limit: 200 })));
+    }
+
+    #[test]
+    fn test_stack_height_branch_to_function() {
+        let wat = r#"
+(module
+    (func $exit_br (result i32)
+        (br 0 (i32.const 1))
+    )
+    (func $exit_br_if (result i32)
+        (br_if 0 (i32.const 1) (i32.const 1))
+    )
+    (func $exit_br_table (result i32)
+        (block $inner (result i32)
+            (br_table $inner 1 (i32.const 1) (i32.const 1))
+        )
+    )
+    (func $run (param $n i32) (result i32)
+        (local $sum i32)
+        (block $done
+            (loop $l
+                (br_if $done (i32.eqz (local.get $n)))
+                (local.set $sum (i32.add (local.get $sum) (call $exit_br)))
+                (local.set $sum (i32.add (local.get $sum) (call $exit_br_if)))
+                (local.set $sum (i32.add (local.get $sum) (call $exit_br_table)))
+                (local.set $n (i32.sub (local.get $n) (i32.const 1)))
+                (br $l)
+            )
+        )
+        (local.get $sum)
+    )
+    (export "run" (func $run))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+        // The loop calls the functions more times than the stack height limit allows
+        let wasmer = make_test_wasmer_with_height(wat, 1, 100000, 100).unwrap();
+        let res = wasmer.call_function("run", &[Value::I32(20)]).unwrap();
+        assert_eq!(res.to_vec(), vec![Value::I32(20 * 3)]);
+
+        let mut guard = wasmer.store_lock.lock().unwrap();
+        assert_eq!(
+            get_stack_height(&mut guard.as_store_mut(), &wasmer.instance),
+            0
+        );
+    }
+
+    #[test]
+    fn test_missing_export() {
+        let wat = r#"
+(module
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+        let wasmer = make_test_wasmer(wat, 1, 1000).unwrap();
+        let res = wasmer.call_function("nope", &[]);
+        assert!(matches!(res, Err(Error::MissingExport { name }) if name == "nope"));
+    }
+
+    #[test]
+    fn test_out_of_gas() {
+        let wat = r#"
+(module
+    (func $loop
+        (loop $l
+            (br $l)
+        )
+    )
+    (export "loop" (func $loop))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+        let wasmer = make_test_wasmer(wat, 1, 1000).unwrap();
+        let res = wasmer.call_function("loop", &[]);
+        assert!(matches!(res, Err(Error::OutOfGas { limit: 1000 })));
+        assert_eq!(res.unwrap_err().code(), 8);
+    }
+
+    #[test]
+    fn test_gas_limit() {
+        let wat = r#"
+(module
+    (func $loop
+        (loop $l
+            (br $l)
+        )
+    )
+    (export "loop" (func $loop))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+        let wasmer = make_test_wasmer(wat, 1, 1000).unwrap();
+        wasmer.set_remaining_points(100).unwrap();
+        assert_eq!(wasmer.remaining_points().unwrap(), 100);
+        let res = wasmer.call_function("loop", &[]);
+        assert!(matches!(res, Err(Error::OutOfGas { limit: 100 })));
+        assert_eq!(wasmer.consumed_points().unwrap(), 100);
+
+        // The remaining points are capped by the metering limit
+        wasmer.set_remaining_points(5000).unwrap();
+        assert_eq!(wasmer.remaining_points().unwrap(), 1000);
+        assert_eq!(wasmer.consumed_points().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_trap_division_by_zero() {
+        let wat = r#"
+(module
+    (func $div (param $param0 i32) (param $param1 i32) (result i32)
+        (i32.div_u
+            (local.get $param0)
+            (local.get $param1)
+        )
+    )
+    (export "div" (func $div))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+        let wasmer = make_test_wasmer(wat, 1, 1000).unwrap();
+        let res = wasmer.call_function("div", &[Value::I32(1), Value::I32(0)]);
+        assert!(matches!(
+            res,
+            Err(Error::Trap {
+                code: TrapCode::IntegerDivisionByZero,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_trap_frames() {
+        let wat = r#"
+(module
+    (func $panic
+        (unreachable)
+    )
+    (func $process
+        (call $panic)
+    )
+    (export "process" (func $process))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+        let wasmer = make_test_wasmer(wat, 1, 1000).unwrap();
+        let res = wasmer.call_function("process", &[]);
+        let frames = match res {
+            Err(Error::Trap {
+                code: TrapCode::UnreachableCodeReached,
+                frames,
+                ..
+            }) => frames,
+            other => panic!("expected a trap, got {other:?}"),
+        };
+
+        let names: Vec<_> = frames
+            .iter()
+            .map(|frame| (frame.func_index, frame.func_name.as_deref()))
+            .collect();
+        assert_eq!(names, vec![(0, Some("panic")), (1, Some("process"))]);
+        assert!(frames[0].module_offset > 0);
+    }
+
+    fn make_test_wasmer_with_profiling(wat: &str) -> Result<WasmerExecutor> {
+        let params = Params {
+            memory_limit_page: 1,
+            metering_limit: 100000,
+            stack_height_limit: 32768,
+            storage_limit: 1024 * 1024,
+            gas_schedule: GasSchedule::default(),
+            module_cache: None,
+            trace: None,
+            profiling: true,
+        };
+        make_test_wasmer_with_params(wat, &params)
+    }
+
+    #[test]
+    fn test_profile() {
+        let wat = r#"
+(module
+    (func $inner (result i32)
+        (i32.const 1)
+    )
+    (func $outer (result i32)
+        (i32.add
+            (call $inner)
+            (call $inner)
+        )
+    )
+    (export "outer" (func $outer))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+        let wasmer = make_test_wasmer_with_profiling(wat).unwrap();
+        let res = wasmer.call_function("outer", &[]).unwrap();
+        assert_eq!(res.to_vec(), vec![Value::I32(2)]);
+
+        let profile = wasmer.profile().unwrap().unwrap();
+        let outer = profile.function("outer").unwrap();
+        let inner = profile.function("inner").unwrap();
+        assert_eq!(outer.calls, 1);
+        assert_eq!(outer.inclusive, wasmer.consumed_points().unwrap());
+        assert_eq!(outer.inclusive, outer.exclusive + inner.inclusive);
+        assert!(outer.callers.is_empty());
+        assert_eq!(inner.calls, 2);
+        assert_eq!(inner.inclusive, inner.exclusive);
+        assert_eq!(
+            inner.callers,
+            vec![crate::profile::CallerProfile {
+                index: outer.index,
+                exclusive: inner.exclusive,
+            }]
+        );
+        assert_eq!(
+            profile.folded(),
+            format!(
+                "outer;inner {}\nouter {}\n",
+                inner.exclusive, outer.exclusive
+            )
+        );
+
+        // Without profiling there is no profile
+        let wasmer = make_test_wasmer(wat, 1, 1000).unwrap();
+        assert!(wasmer.profile().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_profile_branch_to_function() {
+        let wat = r#"
+(module
+    (func $exit_if (param i32) (result i32)
+        (br_if 0 (i32.const 7) (local.get 0))
+        (drop)
+        (i32.const 8)
+    )
+    (func $exit_table (param i32) (result i32)
+        (block (result i32)
+            (br_table 0 1 (i32.const 3) (local.get 0))
+        )
+        (i32.const 1)
+        (i32.add)
+    )
+    (func $exit_return (result i32)
+        (block
+            (return (i32.const 1))
+        )
+        (i32.const 2)
+    )
+    (func $main (result i32)
+        (i32.add
+            (i32.add
+                (call $exit_if (i32.const 1))
+                (call $exit_if (i32.const 0))
+            )
+            (i32.add
+                (i32.add
+                    (call $exit_table (i32.const 0))
+                    (call $exit_table (i32.const 1))
+                )
+                (call $exit_return)
+            )
+        )
+    )
+    (export "main" (func $main))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+        let wasmer = make_test_wasmer_with_profiling(wat).unwrap();
+        let res = wasmer.call_function("main", &[]).unwrap();
+        assert_eq!(res.to_vec(), vec![Value::I32(7 + 8 + 4 + 3 + 1)]);
+
+        let profile = wasmer.profile().unwrap().unwrap();
+        let main = profile.function("main").unwrap();
+        let mut callees_inclusive = 0;
+        for name in ["exit_if", "exit_table", "exit_return"] {
+            let function = profile.function(name).unwrap();
+            assert_eq!(function.inclusive, function.exclusive);
+            assert_eq!(
+                function.callers,
+                vec![crate::profile::CallerProfile {
+                    index: main.index,
+                    exclusive: function.exclusive,
+                }]
+            );
+            callees_inclusive += function.inclusive;
+        }
+        assert_eq!(main.inclusive, wasmer.consumed_points().unwrap());
+        assert_eq!(main.inclusive, main.exclusive + callees_inclusive);
+    }
+
+    #[test]
+    fn test_profile_recursion() {
+        let wat = r#"
+(module
+    (func $fact (param i64) (result i64)
+        (if (result i64) (i64.eqz (local.get 0))
+            (then (i64.const 1))
+            (else
+                (i64.mul
+                    (local.get 0)
+                    (call $fact (i64.sub (local.get 0) (i64.const 1)))
+                )
+            )
+        )
+    )
+    (export "fact" (func $fact))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+        let wasmer = make_test_wasmer_with_profiling(wat).unwrap();
+        let res = wasmer.call_function("fact", &[Value::I64(5)]).unwrap();
+        assert_eq!(res.to_vec(), vec![Value::I64(120)]);
+
+        let profile = wasmer.profile().unwrap().unwrap();
+        let fact = profile.function("fact").unwrap();
+        assert_eq!(fact.calls, 6);
+        // The inclusive points of the recursive calls are measured once
+        assert_eq!(fact.inclusive, wasmer.consumed_points().unwrap());
+        assert_eq!(fact.inclusive, fact.exclusive);
+        // The recursive calls are attributed to the function itself, the rest to the host
+        assert_eq!(fact.callers.len(), 1);
+        assert_eq!(fact.callers[0].index, fact.index);
+        assert!(fact.callers[0].exclusive < fact.exclusive);
+        let host_points = fact.exclusive - fact.callers[0].exclusive;
+        assert_eq!(
+            profile.folded(),
+            format!(
+                "fact;fact {}\nfact {host_points}\n",
+                fact.callers[0].exclusive
+            )
+        );
+
+        let inclusive = fact.inclusive;
+        wasmer.call_function("fact", &[Value::I64(5)]).unwrap();
+        let profile = wasmer.profile().unwrap().unwrap();
+        let fact = profile.function("fact").unwrap();
+        assert_eq!(fact.calls, 12);
+        assert_eq!(fact.inclusive, inclusive * 2);
+    }
+
+    #[test]
+    fn test_profile_indirect_call() {
+        let wat = r#"
+(module
+    (type $callee (func (result i32)))
+    (func $one (result i32)
+        (i32.const 1)
+    )
+    (func $two (result i32)
+        (i32.const 2)
+    )
+    (func $main (param i32) (result i32)
+        (call_indirect (type $callee) (local.get 0))
+    )
+    (table 2 funcref)
+    (elem (i32.const 0) $one $two)
+    (export "main" (func $main))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+        let wasmer = make_test_wasmer_with_profiling(wat).unwrap();
+        let res = wasmer.call_function("main", &[Value::I32(1)]).unwrap();
+        assert_eq!(res.to_vec(), vec![Value::I32(2)]);
+
+        let profile = wasmer.profile().unwrap().unwrap();
+        let main = profile.function("main").unwrap();
+        let two = profile.function("two").unwrap();
+        assert!(profile.function("one").is_none());
+        assert_eq!(
+            two.callers,
+            vec![crate::profile::CallerProfile {
+                index: main.index,
+                exclusive: two.exclusive,
+            }]
+        );
+        assert_eq!(main.inclusive, main.exclusive + two.inclusive);
+    }
+
+    #[test]
+    fn test_profile_other_callers() {
+        use crate::wasmer::profiling::MAX_CALLERS;
+
+        let num_callers = MAX_CALLERS + 2;
+        let mut funcs = String::new();
+        let mut calls = String::new();
+        for i in 0..num_callers {
+            funcs.push_str(&format!("(func $caller_{i} (call $inner))\n"));
+            calls.push_str(&format!("(call $caller_{i})\n"));
+        }
+        let wat = format!(
+            r#"
+(module
+    (func $inner
+        (drop (i32.const 1))
+    )
+    {funcs}
+    (func $main
+        {calls}
+        (call $inner)
+    )
+    (export "main" (func $main))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#
+        );
+
+        let wasmer = make_test_wasmer_with_profiling(&wat).unwrap();
+        wasmer.call_function("main", &[]).unwrap();
+
+        let profile = wasmer.profile().unwrap().unwrap();
+        let inner = profile.function("inner").unwrap();
+        assert_eq!(inner.calls, num_callers as u64 + 1);
+        // Each call of the function consumes the same points
+        let points = inner.exclusive / inner.calls;
+        assert_eq!(inner.callers.len(), MAX_CALLERS);
+        assert!(inner
+            .callers
+            .iter()
+            .all(|caller| caller.exclusive == points));
+        // `main` is the last caller, it is not tracked
+        let main = profile.function("main").unwrap();
+        assert!(inner
+            .callers
+            .iter()
+            .all(|caller| caller.index != main.index));
+        assert_eq!(
+            inner.other_callers,
+            (inner.calls - MAX_CALLERS as u64) * points
+        );
+    }
+
+    #[test]
+    fn test_profile_after_trap() {
+        let wat = r#"
+(module
+    (func $fail (param i32) (result i32)
+        (if (local.get 0)
+            (then (unreachable))
+        )
+        (i32.const 1)
+    )
+    (func $main (param i32) (result i32)
+        (call $fail (local.get 0))
+    )
+    (export "main" (func $main))
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+
+        let wasmer = make_test_wasmer_with_profiling(wat).unwrap();
+        assert!(wasmer.call_function("main", &[Value::I32(1)]).is_err());
+        let consumed_points = wasmer.consumed_points().unwrap();
+
+        // The calls that are active on the trap should not affect the next call
+        let res = wasmer.call_function("main", &[Value::I32(0)]).unwrap();
+        assert_eq!(res.to_vec(), vec![Value::I32(1)]);
+
+        let profile = wasmer.profile().unwrap().unwrap();
+        let main = profile.function("main").unwrap();
+        let fail = profile.function("fail").unwrap();
+        assert_eq!(main.calls, 2);
+        assert_eq!(fail.calls, 2);
+        assert_eq!(
+            main.inclusive,
+            wasmer.consumed_points().unwrap() - consumed_points
+        );
+        assert_eq!(fail.inclusive, fail.exclusive);
+        assert!(main.callers.is_empty());
+        assert_eq!(
+            fail.callers,
+            vec![crate::profile::CallerProfile {
+                index: main.index,
+                exclusive: fail.exclusive,
+            }]
+        );
+    }
 }
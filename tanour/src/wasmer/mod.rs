@@ -1,8 +1,11 @@
 pub mod executor;
 
+mod body_block;
 mod compile;
 mod limiting_tunables;
 mod memory;
 mod native;
+mod profiling;
+mod stack_limit;
 
 pub use executor::*;
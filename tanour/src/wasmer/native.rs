@@ -1,13 +1,22 @@
 use super::{executor::Env, memory};
 use crate::error::Result;
+use crate::trace::TraceEvent;
 use wasmer::{AsStoreRef, FunctionEnvMut};
 
+#[tracing::instrument(level = "debug", skip(func_env))]
 pub(super) fn native_write_storage(
     func_env: FunctionEnvMut<Env>,
     offset: u32,
     ptr: u32,
     len: u32,
 ) -> Result<u32> {
+    let result = write_storage(&func_env, offset, ptr, len);
+    let env = func_env.data();
+    env.record_host_call("write_storage", &[offset, ptr, len], &result);
+    result
+}
+
+fn write_storage(func_env: &FunctionEnvMut<Env>, offset: u32, ptr: u32, len: u32) -> Result<u32> {
     let env = func_env.data();
     let data = memory::read_ptr(
         env.memory.as_ref().unwrap(),
@@ -16,15 +25,24 @@ pub(super) fn native_write_storage(
         len,
     )?;
     env.provider.lock().unwrap().write_storage(offset, &data)?;
+    env.record(TraceEvent::StorageWrite { offset, data });
     Ok(0)
 }
 
+#[tracing::instrument(level = "debug", skip(func_env))]
 pub(super) fn native_read_storage(
     func_env: FunctionEnvMut<Env>,
     offset: u32,
     ptr: u32,
     len: u32,
 ) -> Result<u32> {
+    let result = read_storage(&func_env, offset, ptr, len);
+    let env = func_env.data();
+    env.record_host_call("read_storage", &[offset, ptr, len], &result);
+    result
+}
+
+fn read_storage(func_env: &FunctionEnvMut<Env>, offset: u32, ptr: u32, len: u32) -> Result<u32> {
     let env = func_env.data();
 
     let data = env.provider.lock().unwrap().read_storage(offset, len)?;
@@ -34,19 +52,23 @@ pub(super) fn native_read_storage(
         ptr,
         &data,
     )?;
+    env.record(TraceEvent::StorageRead { offset, data });
     Ok(0)
 }
 
+#[tracing::instrument(level = "debug", skip(func_env))]
 pub(super) fn native_get_param(
     func_env: FunctionEnvMut<Env>,
-    _offset: u32,
-    _ptr: u32,
-    _len: u32,
+    offset: u32,
+    ptr: u32,
+    len: u32,
 ) -> Result<u32> {
     let _address = [0; 21]; // TODO:
-    let _env = func_env.data();
+    let env = func_env.data();
 
     // env.provider.lock().unwrap().exist(&address)?;
 
-    Ok(0)
+    let result = Ok(0);
+    env.record_host_call("get_param", &[offset, ptr, len], &result);
+    result
 }
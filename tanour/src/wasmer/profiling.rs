@@ -0,0 +1,699 @@
+use super::body_block::{body_block_types, BodyBlock, BodyBlockMiddleware};
+use crate::profile::{CallerProfile, FunctionProfile, Profile};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+use wasmer::wasmparser::{
+    Operator, Parser, Payload, Type as WpType, TypeOrFuncType as WpTypeOrFuncType,
+};
+use wasmer::{
+    AsStoreMut, ExportIndex, FunctionMiddleware, GlobalInit, GlobalType, Instance,
+    LocalFunctionIndex, MiddlewareError, MiddlewareReaderState, ModuleMiddleware, Mutability, Type,
+    Value,
+};
+use wasmer_types::{FunctionIndex, GlobalIndex, ModuleInfo};
+
+/// The name of the global that the metering middleware keeps the remaining points.
+const REMAINING_POINTS_GLOBAL: &str = "wasmer_metering_remaining_points";
+
+/// The prefix of the exported globals that keep the profile of the functions.
+const PROFILE_GLOBAL_PREFIX: &str = "tanour_profile";
+
+/// The name of the exported global that keeps the index of the running function.
+const CURRENT_GLOBAL: &str = "tanour_profile_current";
+
+/// The index of the running function when no function is running.
+const HOST: i32 = -1;
+
+/// The maximum number of callers that are tracked for each function.
+/// Each tracked caller adds a global and a check when the function returns,
+/// the points of the other callers are kept together.
+pub(crate) const MAX_CALLERS: usize = 16;
+
+fn profile_global(function_index: u32, counter: &str) -> String {
+    format!("{PROFILE_GLOBAL_PREFIX}_{function_index}_{counter}")
+}
+
+fn caller_global(function_index: u32, caller_index: u32) -> String {
+    format!("{PROFILE_GLOBAL_PREFIX}_{function_index}_caller_{caller_index}")
+}
+
+/// The functions that a local function calls, scanned from the code before compiling.
+#[derive(Debug, Default)]
+struct FunctionCalls {
+    direct: BTreeSet<u32>,
+    indirect: bool,
+}
+
+/// Scans the calls in the function bodies, by the local function index.
+/// The invalid code is left to the compiler to report.
+fn scan_calls(code: &[u8]) -> Vec<FunctionCalls> {
+    let mut functions = Vec::new();
+    for payload in Parser::new(0).parse_all(code) {
+        let body = match payload {
+            Ok(Payload::CodeSectionEntry(body)) => body,
+            Ok(_) => continue,
+            Err(_) => break,
+        };
+
+        let mut calls = FunctionCalls::default();
+        if let Ok(mut reader) = body.get_operators_reader() {
+            while !reader.eof() {
+                match reader.read() {
+                    Ok(Operator::Call { function_index }) => {
+                        calls.direct.insert(function_index);
+                    }
+                    Ok(Operator::CallIndirect { .. }) => calls.indirect = true,
+                    Ok(_) => {}
+                    Err(_) => break,
+                }
+            }
+        }
+        functions.push(calls);
+    }
+    functions
+}
+
+/// The globals that all the functions share.
+#[derive(Debug, Clone, Copy)]
+struct SharedGlobals {
+    /// The remaining points of the metering middleware
+    remaining: GlobalIndex,
+    /// The remaining points when the consumed points are attributed to a function for the last time
+    mark: GlobalIndex,
+    /// The index of the running function
+    current: GlobalIndex,
+    /// The index of the function that called the running function
+    caller: GlobalIndex,
+    /// The points consumed by the running call itself
+    points: GlobalIndex,
+}
+
+/// The globals of a function that keep its profile.
+#[derive(Debug, Clone)]
+struct FunctionGlobals {
+    /// The number of calls
+    calls: GlobalIndex,
+    /// The points consumed by the function and its callees
+    inclusive: GlobalIndex,
+    /// The points consumed by the function itself
+    exclusive: GlobalIndex,
+    /// The number of active calls, to measure the inclusive points of recursive calls once
+    depth: GlobalIndex,
+    /// The remaining points when the outermost call is entered
+    start: GlobalIndex,
+    /// The points consumed by the function itself, by the tracked callers
+    callers: Vec<(u32, GlobalIndex)>,
+    /// The points consumed by the function itself, by the callers that are not tracked
+    others: Option<GlobalIndex>,
+    /// The globals to keep the results while the state of the caller is restored
+    results: Vec<GlobalIndex>,
+}
+
+#[derive(Debug, Default)]
+struct Globals {
+    shared: Option<SharedGlobals>,
+    num_imported_functions: u32,
+    functions: Vec<FunctionGlobals>,
+    /// The block types of the local function bodies.
+    block_types: Vec<WpTypeOrFuncType>,
+}
+
+/// A middleware that measures the metering points that each function consumes.
+///
+/// Each function attributes the points consumed since the last mark to itself
+/// before calling other functions and before returning. The inclusive points are
+/// measured from entering the outermost call to returning from it.
+///
+/// The function body is wrapped in a block, and the state of the caller is kept on
+/// the operand stack under the block, so the points of each call are also attributed
+/// to its caller. The returns and the branches to the function label leave the block,
+/// then the state of the caller is restored. Up to `MAX_CALLERS` callers are tracked
+/// for each function, the points of the other callers are kept together.
+///
+/// It should be pushed after the metering middleware, so the injected operators are not metered.
+#[derive(Debug)]
+pub struct Profiling {
+    /// The calls of the local functions, to find the callers of each function.
+    calls: Vec<FunctionCalls>,
+    globals: Mutex<Globals>,
+}
+
+/// The function-level profiling middleware.
+#[derive(Debug)]
+struct FunctionProfiling {
+    /// The index of the function in the module
+    index: u32,
+    shared: SharedGlobals,
+    globals: FunctionGlobals,
+    /// The block that wraps the function body.
+    body_block: BodyBlock,
+}
+
+impl Profiling {
+    /// Creates the profiling middleware for the code.
+    /// The code is scanned to find the callers of each function.
+    pub fn new(code: &[u8]) -> Self {
+        Self {
+            calls: scan_calls(code),
+            globals: Mutex::new(Globals::default()),
+        }
+    }
+
+    /// Returns the functions that may call each function, by the function index.
+    /// The direct callers come first, then the functions that may call it indirectly.
+    /// The callers beyond `MAX_CALLERS` are not tracked, so the indirect callers are only
+    /// collected until there are more than `MAX_CALLERS` of them.
+    fn callers(&self, module_info: &ModuleInfo) -> BTreeMap<u32, Vec<u32>> {
+        let num_imported_functions = module_info.num_imported_functions as u32;
+
+        // The functions in the tables can be called indirectly
+        let mut table_functions = BTreeSet::new();
+        for initializer in &module_info.table_initializers {
+            table_functions.extend(initializer.elements.iter().map(|index| index.as_u32()));
+        }
+        for elements in module_info.passive_elements.values() {
+            table_functions.extend(elements.iter().map(|index| index.as_u32()));
+        }
+
+        let mut direct_callers: BTreeMap<u32, BTreeSet<u32>> = BTreeMap::new();
+        let mut indirect_callers = Vec::new();
+        for (local_index, calls) in self.calls.iter().enumerate() {
+            let caller = num_imported_functions + local_index as u32;
+            for callee in &calls.direct {
+                direct_callers.entry(*callee).or_default().insert(caller);
+            }
+            if calls.indirect {
+                indirect_callers.push(caller);
+            }
+        }
+
+        let mut callers: BTreeMap<u32, Vec<u32>> = direct_callers
+            .into_iter()
+            .map(|(callee, callers)| (callee, callers.into_iter().collect()))
+            .collect();
+        if !indirect_callers.is_empty() {
+            for callee in table_functions {
+                let callee_callers = callers.entry(callee).or_default();
+                for caller in &indirect_callers {
+                    if callee_callers.len() > MAX_CALLERS {
+                        break;
+                    }
+                    if !callee_callers.contains(caller) {
+                        callee_callers.push(*caller);
+                    }
+                }
+            }
+        }
+        callers
+    }
+}
+
+fn push_global_with(module_info: &mut ModuleInfo, ty: Type, init: GlobalInit) -> GlobalIndex {
+    let index = module_info
+        .globals
+        .push(GlobalType::new(ty, Mutability::Var));
+    module_info.global_initializers.push(init);
+    index
+}
+
+fn push_global(module_info: &mut ModuleInfo, ty: Type) -> Result<GlobalIndex, MiddlewareError> {
+    let init = match ty {
+        Type::I32 => GlobalInit::I32Const(0),
+        Type::I64 => GlobalInit::I64Const(0),
+        Type::F32 => GlobalInit::F32Const(0.0),
+        Type::F64 => GlobalInit::F64Const(0.0),
+        Type::FuncRef | Type::ExternRef => GlobalInit::RefNullConst,
+        Type::V128 => {
+            return Err(MiddlewareError::new(
+                "Profiling",
+                "the functions that return `v128` are not supported",
+            ))
+        }
+    };
+    Ok(push_global_with(module_info, ty, init))
+}
+
+fn export_global(module_info: &mut ModuleInfo, name: String, index: GlobalIndex) {
+    module_info.exports.insert(name, ExportIndex::Global(index));
+}
+
+impl ModuleMiddleware for Profiling {
+    fn generate_function_middleware(
+        &self,
+        local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware> {
+        let globals = self.globals.lock().unwrap();
+        let local_index = local_function_index.as_u32() as usize;
+        Box::new(FunctionProfiling {
+            index: globals.num_imported_functions + local_index as u32,
+            shared: globals
+                .shared
+                .expect("Profiling::generate_function_middleware: globals not set up"),
+            globals: globals.functions[local_index].clone(),
+            body_block: BodyBlock::new(globals.block_types[local_index]),
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) -> Result<(), MiddlewareError> {
+        let mut globals = self.globals.lock().unwrap();
+        if globals.shared.is_some() {
+            return Err(MiddlewareError::new(
+                "Profiling",
+                "attempting to use a `Profiling` middleware from multiple modules",
+            ));
+        }
+
+        let remaining = match module_info.exports.get(REMAINING_POINTS_GLOBAL) {
+            Some(ExportIndex::Global(index)) => *index,
+            _ => {
+                return Err(MiddlewareError::new(
+                    "Profiling",
+                    "the `Metering` middleware should be pushed first",
+                ))
+            }
+        };
+        let shared = SharedGlobals {
+            remaining,
+            mark: push_global(module_info, Type::I64)?,
+            current: push_global_with(module_info, Type::I32, GlobalInit::I32Const(HOST)),
+            caller: push_global_with(module_info, Type::I32, GlobalInit::I32Const(HOST)),
+            points: push_global(module_info, Type::I64)?,
+        };
+        export_global(module_info, CURRENT_GLOBAL.to_string(), shared.current);
+
+        let callers = self.callers(module_info);
+        // The globals that keep the results, by their type
+        let mut result_globals: HashMap<Type, Vec<GlobalIndex>> = HashMap::new();
+
+        let num_imported_functions = module_info.num_imported_functions as u32;
+        let num_functions = module_info.functions.len() as u32;
+        for function_index in num_imported_functions..num_functions {
+            let mut function_globals = FunctionGlobals {
+                calls: push_global(module_info, Type::I64)?,
+                inclusive: push_global(module_info, Type::I64)?,
+                exclusive: push_global(module_info, Type::I64)?,
+                depth: push_global(module_info, Type::I32)?,
+                start: push_global(module_info, Type::I64)?,
+                callers: Vec::new(),
+                others: None,
+                results: Vec::new(),
+            };
+            for (counter, index) in [
+                ("calls", function_globals.calls),
+                ("inclusive", function_globals.inclusive),
+                ("exclusive", function_globals.exclusive),
+                ("depth", function_globals.depth),
+            ] {
+                export_global(module_info, profile_global(function_index, counter), index);
+            }
+
+            let function_callers = callers.get(&function_index).map(Vec::as_slice);
+            let function_callers = function_callers.unwrap_or_default();
+            for caller in function_callers.iter().take(MAX_CALLERS) {
+                let index = push_global(module_info, Type::I64)?;
+                export_global(module_info, caller_global(function_index, *caller), index);
+                function_globals.callers.push((*caller, index));
+            }
+            if function_callers.len() > MAX_CALLERS {
+                let index = push_global(module_info, Type::I64)?;
+                export_global(module_info, profile_global(function_index, "others"), index);
+                function_globals.others = Some(index);
+            }
+
+            let signature_index = module_info.functions[FunctionIndex::from_u32(function_index)];
+            let results = module_info.signatures[signature_index].results().to_vec();
+            let mut counts: HashMap<Type, usize> = HashMap::new();
+            for ty in results {
+                let count = counts.entry(ty).or_default();
+                let type_globals = result_globals.entry(ty).or_default();
+                if type_globals.len() == *count {
+                    type_globals.push(push_global(module_info, ty)?);
+                }
+                function_globals.results.push(type_globals[*count]);
+                *count += 1;
+            }
+
+            globals.functions.push(function_globals);
+        }
+
+        globals.block_types = body_block_types(module_info);
+        globals.num_imported_functions = num_imported_functions;
+        globals.shared = Some(shared);
+        Ok(())
+    }
+}
+
+impl BodyBlockMiddleware for FunctionProfiling {
+    fn body_block(&mut self) -> &mut BodyBlock {
+        &mut self.body_block
+    }
+
+    /// Counts the call, keeps the remaining points if it is the outermost call,
+    /// keeps the state of the caller on the operand stack and marks the points.
+    fn enter(&self, state: &mut MiddlewareReaderState<'_>) {
+        let remaining = self.shared.remaining.as_u32();
+        let current = self.shared.current.as_u32();
+        let caller = self.shared.caller.as_u32();
+        let points = self.shared.points.as_u32();
+        let calls = self.globals.calls.as_u32();
+        let depth = self.globals.depth.as_u32();
+        let start = self.globals.start.as_u32();
+        state.extend(&[
+            Operator::GlobalGet {
+                global_index: calls,
+            },
+            Operator::I64Const { value: 1 },
+            Operator::I64Add,
+            Operator::GlobalSet {
+                global_index: calls,
+            },
+            Operator::GlobalGet {
+                global_index: depth,
+            },
+            Operator::I32Eqz,
+            Operator::If {
+                ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+            },
+            Operator::GlobalGet {
+                global_index: remaining,
+            },
+            Operator::GlobalSet {
+                global_index: start,
+            },
+            Operator::End,
+            Operator::GlobalGet {
+                global_index: depth,
+            },
+            Operator::I32Const { value: 1 },
+            Operator::I32Add,
+            Operator::GlobalSet {
+                global_index: depth,
+            },
+            // The state of the caller, it is restored when the function returns
+            Operator::GlobalGet {
+                global_index: caller,
+            },
+            Operator::GlobalGet {
+                global_index: points,
+            },
+            Operator::GlobalGet {
+                global_index: current,
+            },
+            Operator::GlobalSet {
+                global_index: caller,
+            },
+            Operator::I32Const {
+                value: self.index as i32,
+            },
+            Operator::GlobalSet {
+                global_index: current,
+            },
+            Operator::I64Const { value: 0 },
+            Operator::GlobalSet {
+                global_index: points,
+            },
+        ]);
+        self.mark(state);
+    }
+
+    /// Attributes the points to the function and its caller, adds the inclusive points
+    /// if it is the outermost call and restores the state of the caller.
+    /// It is injected after the function body block, the results of the function
+    /// are on the top of the operand stack and the state of the caller is under them.
+    fn leave(&self, state: &mut MiddlewareReaderState<'_>) {
+        self.attribute(state);
+
+        let current = self.shared.current.as_u32();
+        let caller = self.shared.caller.as_u32();
+        let points = self.shared.points.as_u32();
+        // The points are added to the counter of the caller, the branch skips the rest
+        // of the callers. The calls from the host are not attributed to any caller.
+        state.push_operator(Operator::Block {
+            ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+        });
+        for (caller_index, counter) in &self.globals.callers {
+            state.extend(&[
+                Operator::GlobalGet {
+                    global_index: caller,
+                },
+                Operator::I32Const {
+                    value: *caller_index as i32,
+                },
+                Operator::I32Eq,
+                Operator::If {
+                    ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+                },
+                Operator::GlobalGet {
+                    global_index: counter.as_u32(),
+                },
+                Operator::GlobalGet {
+                    global_index: points,
+                },
+                Operator::I64Add,
+                Operator::GlobalSet {
+                    global_index: counter.as_u32(),
+                },
+                Operator::Br { relative_depth: 1 },
+                Operator::End,
+            ]);
+        }
+        if let Some(others) = self.globals.others {
+            state.extend(&[
+                Operator::GlobalGet {
+                    global_index: caller,
+                },
+                Operator::I32Const { value: HOST },
+                Operator::I32Eq,
+                Operator::BrIf { relative_depth: 0 },
+                Operator::GlobalGet {
+                    global_index: others.as_u32(),
+                },
+                Operator::GlobalGet {
+                    global_index: points,
+                },
+                Operator::I64Add,
+                Operator::GlobalSet {
+                    global_index: others.as_u32(),
+                },
+            ]);
+        }
+        state.push_operator(Operator::End);
+
+        let depth = self.globals.depth.as_u32();
+        let inclusive = self.globals.inclusive.as_u32();
+        state.extend(&[
+            Operator::GlobalGet {
+                global_index: depth,
+            },
+            Operator::I32Const { value: 1 },
+            Operator::I32Sub,
+            Operator::GlobalSet {
+                global_index: depth,
+            },
+            Operator::GlobalGet {
+                global_index: depth,
+            },
+            Operator::I32Eqz,
+            Operator::If {
+                ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+            },
+            Operator::GlobalGet {
+                global_index: inclusive,
+            },
+            Operator::GlobalGet {
+                global_index: self.globals.start.as_u32(),
+            },
+            Operator::GlobalGet {
+                global_index: self.shared.remaining.as_u32(),
+            },
+            Operator::I64Sub,
+            Operator::I64Add,
+            Operator::GlobalSet {
+                global_index: inclusive,
+            },
+            Operator::End,
+        ]);
+
+        // The results are kept aside, so the state of the caller can be popped.
+        for result in self.globals.results.iter().rev() {
+            state.push_operator(Operator::GlobalSet {
+                global_index: result.as_u32(),
+            });
+        }
+        state.extend(&[
+            Operator::GlobalSet {
+                global_index: points,
+            },
+            Operator::GlobalGet {
+                global_index: caller,
+            },
+            Operator::GlobalSet {
+                global_index: current,
+            },
+            Operator::GlobalSet {
+                global_index: caller,
+            },
+        ]);
+        for result in &self.globals.results {
+            state.push_operator(Operator::GlobalGet {
+                global_index: result.as_u32(),
+            });
+        }
+    }
+
+    /// Returning leaves the body block, like the branches to the function label,
+    /// so the state of the caller is restored after the block.
+    fn feed_return(&self, depth: u32, state: &mut MiddlewareReaderState<'_>) {
+        state.push_operator(Operator::Br {
+            relative_depth: depth,
+        });
+    }
+}
+
+impl FunctionProfiling {
+    /// Attributes the points consumed since the last mark to the function and the running call.
+    fn attribute(&self, state: &mut MiddlewareReaderState<'_>) {
+        for counter in [self.globals.exclusive, self.shared.points] {
+            state.extend(&[
+                Operator::GlobalGet {
+                    global_index: counter.as_u32(),
+                },
+                Operator::GlobalGet {
+                    global_index: self.shared.mark.as_u32(),
+                },
+                Operator::GlobalGet {
+                    global_index: self.shared.remaining.as_u32(),
+                },
+                Operator::I64Sub,
+                Operator::I64Add,
+                Operator::GlobalSet {
+                    global_index: counter.as_u32(),
+                },
+            ]);
+        }
+        self.mark(state);
+    }
+
+    /// Marks the remaining points.
+    fn mark(&self, state: &mut MiddlewareReaderState<'_>) {
+        state.extend(&[
+            Operator::GlobalGet {
+                global_index: self.shared.remaining.as_u32(),
+            },
+            Operator::GlobalSet {
+                global_index: self.shared.mark.as_u32(),
+            },
+        ]);
+    }
+}
+
+impl FunctionMiddleware for FunctionProfiling {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        let operator = match self.feed_body(operator, state) {
+            Some(operator) => operator,
+            None => return Ok(()),
+        };
+
+        match operator {
+            Operator::Call { .. } | Operator::CallIndirect { .. } => {
+                self.attribute(state);
+                state.push_operator(operator);
+                // The callee attributes its points before returning
+                self.mark(state);
+            }
+            _ => state.push_operator(operator),
+        }
+        Ok(())
+    }
+}
+
+fn read_counter(ctx: &mut impl AsStoreMut, instance: &Instance, name: &str) -> Option<u64> {
+    let value: i64 = instance
+        .exports
+        .get_global(name)
+        .ok()?
+        .get(ctx)
+        .try_into()
+        .ok()?;
+    Some(value as u64)
+}
+
+/// Returns the profile of the functions, if the module is compiled with the profiling middleware.
+/// The functions that are not called are skipped.
+pub fn get_profile(ctx: &mut impl AsStoreMut, instance: &Instance) -> Option<Profile> {
+    let info = instance.module().info();
+    let num_imported_functions = info.num_imported_functions as u32;
+    let num_functions = info.functions.len() as u32;
+
+    let mut functions = Vec::new();
+    for index in num_imported_functions..num_functions {
+        let calls = read_counter(ctx, instance, &profile_global(index, "calls"))?;
+        if calls == 0 {
+            continue;
+        }
+        let name = info
+            .function_names
+            .get(&FunctionIndex::from_u32(index))
+            .cloned()
+            .unwrap_or_else(|| format!("func[{index}]"));
+
+        let mut callers = Vec::new();
+        for caller_index in num_imported_functions..num_functions {
+            let name = caller_global(index, caller_index);
+            if !info.exports.contains_key(&name) {
+                continue;
+            }
+            let exclusive = read_counter(ctx, instance, &name)?;
+            if exclusive > 0 {
+                callers.push(CallerProfile {
+                    index: caller_index,
+                    exclusive,
+                });
+            }
+        }
+
+        let others = profile_global(index, "others");
+        let other_callers = if info.exports.contains_key(&others) {
+            read_counter(ctx, instance, &others)?
+        } else {
+            0
+        };
+
+        functions.push(FunctionProfile {
+            index,
+            name,
+            calls,
+            inclusive: read_counter(ctx, instance, &profile_global(index, "inclusive"))?,
+            exclusive: read_counter(ctx, instance, &profile_global(index, "exclusive"))?,
+            callers,
+            other_callers,
+        });
+    }
+
+    Some(Profile { functions })
+}
+
+/// Resets the active calls of the profile, if the module is compiled with the profiling middleware.
+///
+/// A trapped execution leaves the active calls as they are,
+/// so they should be reset before calling an exported function.
+pub fn reset_profile(ctx: &mut impl AsStoreMut, instance: &Instance) {
+    let current = match instance.exports.get_global(CURRENT_GLOBAL) {
+        Ok(current) => current,
+        Err(_) => return,
+    };
+    let _ = current.set(ctx, Value::I32(HOST));
+
+    let info = instance.module().info();
+    let num_imported_functions = info.num_imported_functions as u32;
+    let num_functions = info.functions.len() as u32;
+    for index in num_imported_functions..num_functions {
+        if let Ok(depth) = instance.exports.get_global(&profile_global(index, "depth")) {
+            let _ = depth.set(ctx, Value::I32(0));
+        }
+    }
+}
@@ -0,0 +1,545 @@
+use super::body_block::{body_block_types, BodyBlock, BodyBlockMiddleware};
+use std::sync::Mutex;
+use wasmer::wasmparser::{
+    FunctionBody, ImportSectionEntryType, Operator, Parser, Payload, Type as WpType, TypeDef,
+    TypeOrFuncType as WpTypeOrFuncType,
+};
+use wasmer::{
+    AsStoreMut, ExportIndex, FunctionMiddleware, GlobalInit, GlobalType, Instance,
+    LocalFunctionIndex, MiddlewareError, MiddlewareReaderState, ModuleMiddleware, Mutability, Type,
+    Value,
+};
+use wasmer_types::{GlobalIndex, ModuleInfo};
+
+/// The name of the exported global that keeps the current stack height.
+const STACK_HEIGHT_GLOBAL: &str = "tanour_stack_height";
+
+/// The stack height that each function frame keeps besides its values,
+/// like the return address and the saved registers.
+const ACTIVATION_FRAME_COST: u32 = 16;
+
+/// The number of the params and the results of a function type.
+#[derive(Debug, Clone, Copy, Default)]
+struct Signature {
+    params: u32,
+    results: u32,
+}
+
+/// The types of the module, to find the stack effect of the calls and the blocks.
+#[derive(Debug, Default)]
+struct ModuleTypes {
+    signatures: Vec<Signature>,
+    /// The type index of the functions, by the function index
+    functions: Vec<u32>,
+}
+
+impl ModuleTypes {
+    fn signature(&self, type_index: u32) -> Signature {
+        self.signatures
+            .get(type_index as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn function(&self, function_index: u32) -> Signature {
+        self.functions
+            .get(function_index as usize)
+            .map_or_else(Signature::default, |type_index| self.signature(*type_index))
+    }
+
+    fn block(&self, ty: WpTypeOrFuncType) -> Signature {
+        match ty {
+            WpTypeOrFuncType::Type(WpType::EmptyBlockType) => Signature::default(),
+            WpTypeOrFuncType::Type(_) => Signature {
+                params: 0,
+                results: 1,
+            },
+            WpTypeOrFuncType::FuncType(type_index) => self.signature(type_index),
+        }
+    }
+}
+
+/// A block of the function body, to restore the operand stack when the block ends.
+#[derive(Debug)]
+struct ControlFrame {
+    /// The operand stack height when the block is entered, without the block params
+    height: u32,
+    signature: Signature,
+}
+
+/// Measures the maximum operand stack height of a function body.
+#[derive(Debug, Default)]
+struct StackHeight {
+    height: u32,
+    max: u32,
+    frames: Vec<ControlFrame>,
+}
+
+impl StackHeight {
+    fn bottom(&self) -> u32 {
+        self.frames.last().map_or(0, |frame| frame.height)
+    }
+
+    fn push(&mut self, count: u32) {
+        self.height = self.height.saturating_add(count);
+        self.max = self.max.max(self.height);
+    }
+
+    fn pop(&mut self, count: u32) {
+        self.height = self.height.saturating_sub(count).max(self.bottom());
+    }
+
+    /// The rest of the block is unreachable, so its operands are dropped.
+    fn unreachable(&mut self) {
+        self.height = self.bottom();
+    }
+
+    fn enter(&mut self, signature: Signature) {
+        self.pop(signature.params);
+        self.frames.push(ControlFrame {
+            height: self.height,
+            signature,
+        });
+        self.push(signature.params);
+    }
+
+    fn restart(&mut self) {
+        if let Some(frame) = self.frames.last() {
+            let params = frame.signature.params;
+            self.height = frame.height;
+            self.push(params);
+        }
+    }
+
+    fn end(&mut self) {
+        if let Some(frame) = self.frames.pop() {
+            self.height = frame.height;
+            self.push(frame.signature.results);
+        }
+    }
+
+    fn feed(&mut self, operator: &Operator, types: &ModuleTypes) {
+        match operator {
+            Operator::Block { ty } | Operator::Loop { ty } => self.enter(types.block(*ty)),
+            Operator::If { ty } => {
+                self.pop(1);
+                self.enter(types.block(*ty));
+            }
+            Operator::Else => self.restart(),
+            Operator::End => self.end(),
+            Operator::Unreachable
+            | Operator::Br { .. }
+            | Operator::BrTable { .. }
+            | Operator::Return => self.unreachable(),
+            Operator::Call { function_index } => {
+                let signature = types.function(*function_index);
+                self.pop(signature.params);
+                self.push(signature.results);
+            }
+            Operator::CallIndirect { index, .. } => {
+                let signature = types.signature(*index);
+                self.pop(signature.params.saturating_add(1));
+                self.push(signature.results);
+            }
+            Operator::I32Const { .. }
+            | Operator::I64Const { .. }
+            | Operator::F32Const { .. }
+            | Operator::F64Const { .. }
+            | Operator::V128Const { .. }
+            | Operator::LocalGet { .. }
+            | Operator::GlobalGet { .. }
+            | Operator::MemorySize { .. }
+            | Operator::TableSize { .. }
+            | Operator::RefNull { .. }
+            | Operator::RefFunc { .. } => self.push(1),
+            // The binary operators pop two values and push one
+            Operator::Drop
+            | Operator::LocalSet { .. }
+            | Operator::GlobalSet { .. }
+            | Operator::BrIf { .. }
+            | Operator::TableGrow { .. }
+            | Operator::I32Eq
+            | Operator::I32Ne
+            | Operator::I32LtS
+            | Operator::I32LtU
+            | Operator::I32GtS
+            | Operator::I32GtU
+            | Operator::I32LeS
+            | Operator::I32LeU
+            | Operator::I32GeS
+            | Operator::I32GeU
+            | Operator::I64Eq
+            | Operator::I64Ne
+            | Operator::I64LtS
+            | Operator::I64LtU
+            | Operator::I64GtS
+            | Operator::I64GtU
+            | Operator::I64LeS
+            | Operator::I64LeU
+            | Operator::I64GeS
+            | Operator::I64GeU
+            | Operator::F32Eq
+            | Operator::F32Ne
+            | Operator::F32Lt
+            | Operator::F32Gt
+            | Operator::F32Le
+            | Operator::F32Ge
+            | Operator::F64Eq
+            | Operator::F64Ne
+            | Operator::F64Lt
+            | Operator::F64Gt
+            | Operator::F64Le
+            | Operator::F64Ge
+            | Operator::I32Add
+            | Operator::I32Sub
+            | Operator::I32Mul
+            | Operator::I32DivS
+            | Operator::I32DivU
+            | Operator::I32RemS
+            | Operator::I32RemU
+            | Operator::I32And
+            | Operator::I32Or
+            | Operator::I32Xor
+            | Operator::I32Shl
+            | Operator::I32ShrS
+            | Operator::I32ShrU
+            | Operator::I32Rotl
+            | Operator::I32Rotr
+            | Operator::I64Add
+            | Operator::I64Sub
+            | Operator::I64Mul
+            | Operator::I64DivS
+            | Operator::I64DivU
+            | Operator::I64RemS
+            | Operator::I64RemU
+            | Operator::I64And
+            | Operator::I64Or
+            | Operator::I64Xor
+            | Operator::I64Shl
+            | Operator::I64ShrS
+            | Operator::I64ShrU
+            | Operator::I64Rotl
+            | Operator::I64Rotr
+            | Operator::F32Add
+            | Operator::F32Sub
+            | Operator::F32Mul
+            | Operator::F32Div
+            | Operator::F32Min
+            | Operator::F32Max
+            | Operator::F32Copysign
+            | Operator::F64Add
+            | Operator::F64Sub
+            | Operator::F64Mul
+            | Operator::F64Div
+            | Operator::F64Min
+            | Operator::F64Max
+            | Operator::F64Copysign => self.pop(1),
+            Operator::Select
+            | Operator::TypedSelect { .. }
+            | Operator::TableSet { .. }
+            | Operator::I32Store { .. }
+            | Operator::I64Store { .. }
+            | Operator::F32Store { .. }
+            | Operator::F64Store { .. }
+            | Operator::I32Store8 { .. }
+            | Operator::I32Store16 { .. }
+            | Operator::I64Store8 { .. }
+            | Operator::I64Store16 { .. }
+            | Operator::I64Store32 { .. } => self.pop(2),
+            Operator::MemoryInit { .. }
+            | Operator::MemoryCopy { .. }
+            | Operator::MemoryFill { .. }
+            | Operator::TableInit { .. }
+            | Operator::TableCopy { .. }
+            | Operator::TableFill { .. } => self.pop(3),
+            // The other operators don't raise the stack height,
+            // like the unary operators, the loads and the conversions
+            _ => {}
+        }
+    }
+}
+
+/// Returns the stack height that the frame of the function keeps:
+/// its params, its locals and its maximum operand stack height.
+fn frame_cost(body: &FunctionBody, signature: Signature, types: &ModuleTypes) -> u32 {
+    let mut locals: u32 = 0;
+    if let Ok(mut reader) = body.get_locals_reader() {
+        for _ in 0..reader.get_count() {
+            match reader.read() {
+                Ok((count, _)) => locals = locals.saturating_add(count),
+                Err(_) => break,
+            }
+        }
+    }
+
+    let mut stack = StackHeight::default();
+    if let Ok(mut reader) = body.get_operators_reader() {
+        while !reader.eof() {
+            match reader.read() {
+                Ok(operator) => stack.feed(&operator, types),
+                Err(_) => break,
+            }
+        }
+    }
+
+    ACTIVATION_FRAME_COST
+        .saturating_add(signature.params)
+        .saturating_add(locals)
+        .saturating_add(stack.max)
+}
+
+/// Scans the frame cost of the local functions, by the local function index.
+/// The invalid code is left to the compiler to report.
+fn scan_frame_costs(code: &[u8]) -> Vec<u32> {
+    let mut types = ModuleTypes::default();
+    let mut num_imported_functions = 0;
+    let mut costs = Vec::new();
+    for payload in Parser::new(0).parse_all(code) {
+        match payload {
+            Ok(Payload::TypeSection(reader)) => {
+                for ty in reader {
+                    let signature = match ty {
+                        Ok(TypeDef::Func(func)) => Signature {
+                            params: func.params.len() as u32,
+                            results: func.returns.len() as u32,
+                        },
+                        _ => Signature::default(),
+                    };
+                    types.signatures.push(signature);
+                }
+            }
+            Ok(Payload::ImportSection(reader)) => {
+                for import in reader.into_iter().flatten() {
+                    if let ImportSectionEntryType::Function(type_index) = import.ty {
+                        types.functions.push(type_index);
+                        num_imported_functions += 1;
+                    }
+                }
+            }
+            Ok(Payload::FunctionSection(reader)) => {
+                types.functions.extend(reader.into_iter().flatten());
+            }
+            Ok(Payload::CodeSectionEntry(body)) => {
+                let function_index = num_imported_functions + costs.len() as u32;
+                let signature = types.function(function_index);
+                costs.push(frame_cost(&body, signature, &types));
+            }
+            Ok(_) => {}
+            Err(_) => break,
+        }
+    }
+    costs
+}
+
+/// A middleware that limits the stack height of the contract.
+///
+/// The frame cost of each function is its params, its locals and its maximum
+/// operand stack height, measured from the code before compiling. Each function
+/// adds its frame cost to a global counter when it is entered and subtracts it
+/// when it returns. If the counter exceeds the limit, the execution traps.
+/// Unlike the native stack guard, the limit doesn't depend on the platform or the compiler.
+///
+/// The function body is wrapped in a block, so the branches to the function label
+/// subtract the frame cost as well.
+#[derive(Debug)]
+pub struct StackLimit {
+    /// The maximum stack height that the contract can reach.
+    limit: u32,
+    /// The frame cost of the local functions.
+    costs: Vec<u32>,
+    /// The global index of the stack height counter.
+    global_index: Mutex<Option<GlobalIndex>>,
+    /// The block types of the local function bodies.
+    block_types: Mutex<Vec<WpTypeOrFuncType>>,
+}
+
+/// The function-level stack limit middleware.
+#[derive(Debug)]
+struct FunctionStackLimit {
+    /// The maximum stack height that the contract can reach.
+    limit: u32,
+    /// The frame cost of the function.
+    cost: u32,
+    /// The global index of the stack height counter.
+    global_index: GlobalIndex,
+    /// The block that wraps the function body.
+    body_block: BodyBlock,
+}
+
+impl StackLimit {
+    /// Creates the stack limit middleware for the code.
+    /// The code is scanned to find the frame cost of each function.
+    pub fn new(limit: u32, code: &[u8]) -> Self {
+        Self {
+            limit,
+            costs: scan_frame_costs(code),
+            global_index: Mutex::new(None),
+            block_types: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl ModuleMiddleware for StackLimit {
+    fn generate_function_middleware(
+        &self,
+        local_function_index: LocalFunctionIndex,
+    ) -> Box<dyn FunctionMiddleware> {
+        Box::new(FunctionStackLimit {
+            limit: self.limit,
+            cost: self
+                .costs
+                .get(local_function_index.as_u32() as usize)
+                .copied()
+                .unwrap_or(ACTIVATION_FRAME_COST),
+            global_index: self
+                .global_index
+                .lock()
+                .unwrap()
+                .expect("StackLimit::generate_function_middleware: global index not set up"),
+            body_block: BodyBlock::new(
+                self.block_types.lock().unwrap()[local_function_index.as_u32() as usize],
+            ),
+        })
+    }
+
+    fn transform_module_info(&self, module_info: &mut ModuleInfo) -> Result<(), MiddlewareError> {
+        let mut global_index = self.global_index.lock().unwrap();
+        if global_index.is_some() {
+            return Err(MiddlewareError::new(
+                "StackLimit",
+                "attempting to use a `StackLimit` middleware from multiple modules",
+            ));
+        }
+
+        let index = module_info
+            .globals
+            .push(GlobalType::new(Type::I64, Mutability::Var));
+        module_info
+            .global_initializers
+            .push(GlobalInit::I64Const(0));
+        module_info
+            .exports
+            .insert(STACK_HEIGHT_GLOBAL.to_string(), ExportIndex::Global(index));
+
+        *global_index = Some(index);
+        *self.block_types.lock().unwrap() = body_block_types(module_info);
+        Ok(())
+    }
+}
+
+impl BodyBlockMiddleware for FunctionStackLimit {
+    fn body_block(&mut self) -> &mut BodyBlock {
+        &mut self.body_block
+    }
+
+    /// Adds the frame cost to the stack height and traps if it exceeds the limit.
+    fn enter(&self, state: &mut MiddlewareReaderState<'_>) {
+        let global_index = self.global_index.as_u32();
+        state.extend(&[
+            Operator::GlobalGet { global_index },
+            Operator::I64Const {
+                value: self.cost as i64,
+            },
+            Operator::I64Add,
+            Operator::GlobalSet { global_index },
+            Operator::GlobalGet { global_index },
+            Operator::I64Const {
+                value: self.limit as i64,
+            },
+            Operator::I64GtU,
+            Operator::If {
+                ty: WpTypeOrFuncType::Type(WpType::EmptyBlockType),
+            },
+            Operator::Unreachable,
+            Operator::End,
+        ]);
+    }
+
+    /// Subtracts the frame cost from the stack height.
+    fn leave(&self, state: &mut MiddlewareReaderState<'_>) {
+        let global_index = self.global_index.as_u32();
+        state.extend(&[
+            Operator::GlobalGet { global_index },
+            Operator::I64Const {
+                value: self.cost as i64,
+            },
+            Operator::I64Sub,
+            Operator::GlobalSet { global_index },
+        ]);
+    }
+}
+
+impl FunctionMiddleware for FunctionStackLimit {
+    fn feed<'a>(
+        &mut self,
+        operator: Operator<'a>,
+        state: &mut MiddlewareReaderState<'a>,
+    ) -> Result<(), MiddlewareError> {
+        if let Some(operator) = self.feed_body(operator, state) {
+            state.push_operator(operator);
+        }
+        Ok(())
+    }
+}
+
+/// Returns the current stack height of the instance.
+pub fn get_stack_height(ctx: &mut impl AsStoreMut, instance: &Instance) -> u64 {
+    let height: i64 = instance
+        .exports
+        .get_global(STACK_HEIGHT_GLOBAL)
+        .expect("Can't get `tanour_stack_height` from Instance")
+        .get(ctx)
+        .try_into()
+        .expect("`tanour_stack_height` from Instance has wrong type");
+
+    height as u64
+}
+
+/// Resets the stack height of the instance.
+///
+/// A trapped execution leaves the counter as it is,
+/// so it should be reset before calling an exported function.
+pub fn reset_stack_height(ctx: &mut impl AsStoreMut, instance: &Instance) {
+    instance
+        .exports
+        .get_global(STACK_HEIGHT_GLOBAL)
+        .expect("Can't get `tanour_stack_height` from Instance")
+        .set(ctx, Value::I64(0))
+        .expect("Can't set `tanour_stack_height` in Instance");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame_cost() {
+        let wat = r#"
+(module
+    (func $add (param $a i32) (param $b i32) (result i32)
+        (i32.add (local.get $a) (local.get $b))
+    )
+    (func $locals (local i64 i64) (local f32)
+        (drop (i32.add (i32.const 1) (call $add (i32.const 2) (i32.const 3))))
+    )
+    (func $unreachable (result i32)
+        (block $b (result i32)
+            (br $b (i32.const 1))
+            (i32.const 2)
+            (i32.const 3)
+            (i32.add)
+        )
+    )
+)"#;
+        let code = wat::parse_str(wat).unwrap();
+        let costs = scan_frame_costs(&code);
+        assert_eq!(
+            costs,
+            vec![
+                ACTIVATION_FRAME_COST + 2 + 2,
+                ACTIVATION_FRAME_COST + 3 + 3,
+                ACTIVATION_FRAME_COST + 2,
+            ]
+        );
+    }
+}
@@ -1,23 +1,46 @@
 use hex_literal::hex;
+use std::sync::Arc;
 use tanour::{
     blockchain_api::MockBlockchainAPI,
+    cache::ModuleCache,
+    chain::{FileChain, InMemoryChain},
     contract::{Contract, Params},
+    gas::GasSchedule,
+    trace::{replay, TraceEvent, TraceRecorder},
+    CONTRACT_ADDRESS_TYPE,
 };
 use test_contract::message::{Error, InstantiateMsg, ProcMsg, QueryMsg, QueryRsp};
 
-fn make_test_contract(wat: &[u8], memory_limit_page: u32, metering_limit: u64) -> Contract {
-    let code = wat::parse_bytes(wat).unwrap().to_vec();
-    let address = rand::random();
-    let params = Params {
-        memory_limit_page,
-        metering_limit,
-    };
-
+fn make_test_api() -> Box<MockBlockchainAPI> {
     let mut api = Box::new(MockBlockchainAPI::new());
     api.expect_page_size().returning(|| Ok(256));
+    api.expect_storage_size().returning(|| Ok(0));
     api.expect_read_page().returning(|_| Ok(vec![0; 256]));
+    api.expect_read_pages()
+        .returning(|page_nos| Ok(vec![vec![0; 256]; page_nos.len()]));
+    api.expect_exist().returning(|_| Ok(false));
+    api
+}
+
+fn make_test_params(memory_limit_page: u32, metering_limit: u64) -> Params {
+    Params {
+        memory_limit_page,
+        metering_limit,
+        stack_height_limit: 32768,
+        storage_limit: 1024 * 1024,
+        gas_schedule: GasSchedule::default(),
+        module_cache: None,
+        trace: None,
+        profiling: false,
+    }
+}
+
+fn make_test_contract(wat: &[u8], memory_limit_page: u32, metering_limit: u64) -> Contract {
+    let code = wat::parse_bytes(wat).unwrap().to_vec();
+    let address = rand::random();
+    let params = make_test_params(memory_limit_page, metering_limit);
 
-    Contract::new(api, &address, &code, params).unwrap()
+    Contract::new(make_test_api(), &address, &code, params).unwrap()
 }
 
 #[test]
@@ -90,3 +113,386 @@ fn test_hash_blake2b() {
     assert_eq!(contract.consumed_points().unwrap(), 28598);
     assert!(!contract.exhausted().unwrap());
 }
+
+#[test]
+fn test_deploy() {
+    let code = include_bytes!("../../test-contract/wasm/test_contract.wasm");
+    let sender = rand::random();
+    let salt = [1, 2, 3];
+
+    let mut contract = Contract::deploy(
+        make_test_api(),
+        &sender,
+        &salt,
+        code,
+        make_test_params(16, 100000),
+    )
+    .unwrap();
+    assert_eq!(contract.address()[0], CONTRACT_ADDRESS_TYPE);
+
+    let arg = InstantiateMsg {};
+    let encoded_arg = minicbor::to_vec(arg).unwrap();
+    let encoded_res = contract.call_instantiate(&encoded_arg).unwrap();
+    let res = minicbor::decode::<Result<(), Error>>(&encoded_res).unwrap();
+    assert!(res.is_ok());
+    assert!(!contract.updated_pages().unwrap().is_empty());
+    assert!(contract.storage_growth().unwrap() > 0);
+
+    // Deploying the same code with the same salt should result the same address
+    assert_eq!(
+        contract.address(),
+        &tanour::contract_address(&sender, code, &salt)
+    );
+
+    // Deploying again should fail, since the contract exists
+    let mut api = Box::new(MockBlockchainAPI::new());
+    api.expect_exist().returning(|_| Ok(true));
+    let res = Contract::deploy(api, &sender, &salt, code, make_test_params(16, 100000));
+    let address = hex::encode(contract.address());
+    assert!(matches!(
+        res,
+        Err(tanour::error::Error::ContractExists { address: a }) if a == address
+    ));
+
+    // Deploying the same code with a different salt should result a different address
+    let contract_3 = Contract::deploy(
+        make_test_api(),
+        &sender,
+        &[4, 5, 6],
+        code,
+        make_test_params(16, 100000),
+    )
+    .unwrap();
+    assert_ne!(contract.address(), contract_3.address());
+}
+
+#[test]
+fn test_deploy_invalid_code() {
+    let wat = br#"
+(module
+    (memory $0 1)
+    (export "memory" (memory $0))
+)"#;
+    let code = wat::parse_bytes(wat).unwrap().to_vec();
+    let sender = rand::random();
+
+    let res = Contract::deploy(
+        make_test_api(),
+        &sender,
+        &[],
+        &code,
+        make_test_params(16, 100000),
+    );
+    assert!(matches!(
+        res,
+        Err(tanour::error::Error::MissingExport { name }) if name == "instantiate"
+    ));
+}
+
+#[test]
+fn test_deploy_file_chain() {
+    let code = include_bytes!("../../test-contract/wasm/test_contract.wasm");
+    let name: u64 = rand::random();
+    let dir = std::env::temp_dir().join(format!("tanour-{name:x}"));
+    let sender = rand::random();
+    let salt = [1, 2, 3];
+    let address = tanour::contract_address(&sender, code, &salt);
+
+    let chain = FileChain::new(&dir, &address, 256).unwrap();
+    let mut contract = Contract::deploy(
+        Box::new(chain),
+        &sender,
+        &salt,
+        code,
+        make_test_params(16, 100000),
+    )
+    .unwrap();
+    let arg = InstantiateMsg {};
+    let encoded_arg = minicbor::to_vec(arg).unwrap();
+    contract.call_instantiate(&encoded_arg).unwrap();
+    contract.commit().unwrap();
+
+    // Deploying again should fail, since the storage of the contract is written
+    let chain = FileChain::new(&dir, &address, 256).unwrap();
+    let res = Contract::deploy(
+        Box::new(chain),
+        &sender,
+        &salt,
+        code,
+        make_test_params(16, 100000),
+    );
+    assert!(matches!(
+        res,
+        Err(tanour::error::Error::ContractExists { .. })
+    ));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_in_memory_chain() {
+    let code = include_bytes!("../../test-contract/wasm/test_contract.wasm");
+    let chain = InMemoryChain::new(256);
+    let address = rand::random();
+
+    let mut contract = Contract::new(
+        Box::new(chain.clone()),
+        &address,
+        code,
+        make_test_params(16, 100000),
+    )
+    .unwrap();
+    let arg = InstantiateMsg {};
+    let encoded_arg = minicbor::to_vec(arg).unwrap();
+    contract.call_instantiate(&encoded_arg).unwrap();
+
+    let arg = ProcMsg::SetMessage {
+        msg: "hello world!".to_string(),
+    };
+    let encoded_arg = minicbor::to_vec(arg).unwrap();
+    contract.call_process(&encoded_arg).unwrap();
+    contract.commit().unwrap();
+
+    // Loading the contract again from the chain
+    let mut contract = Contract::new(
+        Box::new(chain),
+        &address,
+        code,
+        make_test_params(16, 100000),
+    )
+    .unwrap();
+    let arg = QueryMsg::GetMessage;
+    let encoded_arg = minicbor::to_vec(arg).unwrap();
+    let encoded_res = contract.call_query(&encoded_arg).unwrap();
+    let res = minicbor::decode::<Result<QueryRsp, Error>>(&encoded_res).unwrap();
+    assert_eq!(res.unwrap(), QueryRsp::String("hello world!".to_string()));
+}
+
+#[test]
+fn test_profile() {
+    let code = include_bytes!("../../test-contract/wasm/test_contract.wasm");
+    let mut params = make_test_params(16, 100000);
+    params.profiling = true;
+    let mut contract = Contract::new(make_test_api(), &rand::random(), code, params).unwrap();
+
+    let arg = InstantiateMsg {};
+    contract
+        .call_instantiate(&minicbor::to_vec(arg).unwrap())
+        .unwrap();
+    let consumed_points = contract.consumed_points().unwrap();
+    let arg = ProcMsg::Null;
+    contract
+        .call_process(&minicbor::to_vec(arg).unwrap())
+        .unwrap();
+
+    // The function names are read from the name section of the code
+    let profile = contract.profile().unwrap().unwrap();
+    let process = profile.function("process").unwrap();
+    assert_eq!(process.calls, 1);
+    assert!(process.inclusive > 0);
+    assert!(process.inclusive <= contract.consumed_points().unwrap() - consumed_points);
+    assert_eq!(profile.function("instantiate").unwrap().calls, 1);
+    assert!(profile.function("allocate").unwrap().calls >= 2);
+
+    let folded = profile.folded();
+    assert!(folded.lines().any(|line| line.contains(';')));
+    assert!(folded.lines().any(|line| line.starts_with("allocate ")));
+}
+
+#[test]
+fn test_module_cache() {
+    let code = include_bytes!("../../test-contract/wasm/test_contract.wasm");
+    let cache = Arc::new(ModuleCache::new(10, None).unwrap());
+
+    let mut consumed_points = Vec::new();
+    for i in 0..2 {
+        let mut params = make_test_params(16, 100000);
+        params.module_cache = Some(cache.clone());
+        let mut contract = Contract::new(make_test_api(), &rand::random(), code, params).unwrap();
+
+        let arg = QueryMsg::Divider { a: 10, b: 2 };
+        let encoded_arg = minicbor::to_vec(arg).unwrap();
+        let encoded_res = contract.call_query(&encoded_arg).unwrap();
+        let res = minicbor::decode::<Result<QueryRsp, Error>>(&encoded_res).unwrap();
+        assert_eq!(res.unwrap(), QueryRsp::Int32(5));
+        consumed_points.push(contract.consumed_points().unwrap());
+
+        // The module is compiled only for the first time
+        assert_eq!(contract.compile_info().cache_hit, i > 0);
+    }
+
+    // The cached module should be metered the same
+    assert_eq!(consumed_points[0], consumed_points[1]);
+}
+
+#[test]
+fn test_trace() {
+    let code = include_bytes!("../../test-contract/wasm/test_contract.wasm");
+    let recorder = TraceRecorder::new();
+    let mut params = make_test_params(16, 100000);
+    params.trace = Some(recorder.clone());
+    let mut contract = Contract::new(make_test_api(), &rand::random(), code, params).unwrap();
+
+    let arg = InstantiateMsg {};
+    let encoded_arg = minicbor::to_vec(arg).unwrap();
+    contract.call_instantiate(&encoded_arg).unwrap();
+
+    let arg = ProcMsg::SetMessage {
+        msg: "hello world!".to_string(),
+    };
+    let encoded_arg = minicbor::to_vec(arg).unwrap();
+    let encoded_res = contract.call_process(&encoded_arg).unwrap();
+
+    let trace = contract.trace().unwrap();
+    assert_eq!(trace, recorder.trace());
+    assert!(trace.events.contains(&TraceEvent::Call {
+        function: "process".to_string(),
+        arg: encoded_arg,
+    }));
+    assert_eq!(
+        trace.events.last(),
+        Some(&TraceEvent::Return {
+            function: "process".to_string(),
+            data: encoded_res,
+            error: None,
+            consumed_points: contract.consumed_points().unwrap(),
+        })
+    );
+    assert!(trace
+        .events
+        .iter()
+        .any(|event| matches!(event, TraceEvent::StorageWrite { .. })));
+    assert!(trace.events.iter().any(|event| matches!(
+        event,
+        TraceEvent::HostCall { function, result: Some(0), .. } if function == "write_storage"
+    )));
+    assert!(trace
+        .events
+        .iter()
+        .any(|event| matches!(event, TraceEvent::Gas { .. })));
+
+    #[cfg(feature = "codec")]
+    {
+        let path =
+            std::env::temp_dir().join(format!("tanour-trace-{}.json", rand::random::<u64>()));
+        trace.write_file(&path).unwrap();
+        assert_eq!(tanour::trace::Trace::read_file(&path).unwrap(), trace);
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+fn test_replay() {
+    let code = include_bytes!("../../test-contract/wasm/test_contract.wasm");
+    let address = rand::random();
+    let recorder = TraceRecorder::new();
+    let mut params = make_test_params(16, 100000);
+    params.trace = Some(recorder.clone());
+    let mut contract =
+        Contract::new(Box::new(InMemoryChain::new(256)), &address, code, params).unwrap();
+
+    let arg = InstantiateMsg {};
+    contract
+        .call_instantiate(&minicbor::to_vec(arg).unwrap())
+        .unwrap();
+    let arg = ProcMsg::SetMessage {
+        msg: "hello world!".to_string(),
+    };
+    contract
+        .call_process(&minicbor::to_vec(arg).unwrap())
+        .unwrap();
+    let arg = QueryMsg::GetMessage;
+    contract
+        .call_query(&minicbor::to_vec(arg).unwrap())
+        .unwrap();
+    let arg = QueryMsg::Divider { a: 1, b: 0 };
+    contract
+        .call_query(&minicbor::to_vec(arg).unwrap())
+        .unwrap();
+
+    let mut trace = recorder.trace();
+    let header = trace.header.clone().unwrap();
+    assert_eq!(header.address, address.to_vec());
+    assert_eq!(header.metering_limit, 100000);
+
+    let replayed = replay(&address, code, &trace, make_test_params(16, 100000)).unwrap();
+    assert_eq!(replayed.calls().count(), 4);
+    assert_eq!(replayed.header, Some(header));
+
+    // The recorded params are used, instead of the given params
+    let mut params = make_test_params(16, 100000);
+    params.gas_schedule.call_cost = 10;
+    assert!(replay(&address, code, &trace, params).is_ok());
+
+    // Replaying another contract should fail
+    assert!(matches!(
+        replay(&rand::random(), code, &trace, make_test_params(16, 100000)),
+        Err(tanour::error::Error::ReplayError { .. })
+    ));
+
+    // Replaying with a different gas schedule should fail
+    let mut tampered = trace.clone();
+    tampered.header.as_mut().unwrap().gas_schedule.call_cost = 10;
+    assert!(matches!(
+        replay(&address, code, &tampered, make_test_params(16, 100000)),
+        Err(tanour::error::Error::ReplayError { .. })
+    ));
+
+    // Tampering the recorded result should fail
+    for event in trace.events.iter_mut() {
+        if let TraceEvent::Return { data, .. } = event {
+            data.push(0);
+        }
+    }
+    assert!(matches!(
+        replay(&address, code, &trace, make_test_params(16, 100000)),
+        Err(tanour::error::Error::ReplayError { .. })
+    ));
+}
+
+#[test]
+fn test_malformed_abi() {
+    let mut code = include_bytes!("../../test-contract/wasm/test_contract.wasm").to_vec();
+    // Appending a `pactus_abi` custom section that is not a valid JSON
+    let name = b"pactus_abi";
+    let data = b"{not json";
+    code.push(0);
+    code.push((1 + name.len() + data.len()) as u8);
+    code.push(name.len() as u8);
+    code.extend_from_slice(name);
+    code.extend_from_slice(data);
+
+    let mut contract = Contract::new(
+        make_test_api(),
+        &rand::random(),
+        &code,
+        make_test_params(16, 100000),
+    )
+    .unwrap();
+
+    // The deployed contract can be executed, but its ABI can't be used
+    let arg = QueryMsg::Divider { a: 10, b: 2 };
+    let encoded_res = contract
+        .call_query(&minicbor::to_vec(arg).unwrap())
+        .unwrap();
+    let res = minicbor::decode::<Result<QueryRsp, Error>>(&encoded_res).unwrap();
+    assert_eq!(res.unwrap(), QueryRsp::Int32(5));
+
+    #[cfg(feature = "codec")]
+    {
+        assert!(matches!(
+            contract.abi(),
+            Err(tanour::error::Error::AbiError { .. })
+        ));
+
+        // A new contract can't be deployed with the malformed ABI
+        let res = Contract::deploy(
+            make_test_api(),
+            &rand::random(),
+            &[],
+            &code,
+            make_test_params(16, 100000),
+        );
+        assert!(matches!(res, Err(tanour::error::Error::AbiError { .. })));
+    }
+}